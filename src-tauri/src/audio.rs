@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+
+use super::{cache, getVideoData, Playback, Scene, PROJECT_DIR, TEMP_DIR};
+
+// sample rate of the ducking envelope; plenty for gain changes over tens of milliseconds
+const ENVELOPE_RATE: u32 = 1000;
+
+/// An audio file placed on the scene's timeline.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AudioTrack {
+    pub source: String,        // relative to public/, like Scene.audio
+    pub offset: Option<f64>,   // scene time (s) at which the track starts
+    pub inPoint: Option<f64>,  // track time (s)
+    pub outPoint: Option<f64>, // track time (s)
+    pub gain: Option<f64>,     // dB
+}
+
+/// Music mixed under the dialogue, and ducked while anyone is speaking.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MusicBed {
+    pub source: String,        // relative to public/
+    pub offset: Option<f64>,   // scene time (s) at which the music starts
+    pub inPoint: Option<f64>,  // track time (s)
+    pub outPoint: Option<f64>, // track time (s)
+    pub gain: Option<f64>,     // dB
+    pub r#loop: Option<bool>,  // repeat until the end of the scene
+    pub ducking: Option<Ducking>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Ducking {
+    pub mode: Option<String>,   // "spans" (default) | "sidechain"
+    pub depth: Option<f64>,     // dB the music drops under speech, default 12
+    pub attack: Option<f64>,    // s to duck, default 0.2
+    pub release: Option<f64>,   // s to come back up, default 0.8
+    pub threshold: Option<f64>, // sidechain only: dialogue level (dBFS) that starts ducking, default -40
+    pub spans: Option<HashMap<String, Vec<Span>>>, // spans mode: speaker -> speech, from analyseAudio's diarisation
+}
+
+/// Speech, in scene time (s).
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct Span {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// The scene's audio mixed down to one stream, labelled `[aout]`.
+pub struct AudioMix {
+    pub inputs: Vec<String>,
+    pub filter: String,
+}
+
+impl AudioMix {
+    /// How many inputs the mix adds.
+    pub fn inputCount(&self) -> usize {
+        self.inputs.iter().filter(|arg| *arg == "-i").count()
+    }
+
+    /// Arguments to put after every input, mapping the mix into the output.
+    pub fn outputArgs(&self) -> Vec<String> {
+        vec!["-filter_complex".into(), self.filter.clone(), "-map".into(), "[aout]".into()]
+    }
+}
+
+// one input to the mix, with its placement on the scene timeline
+struct Source {
+    path: PathBuf,
+    offset: f64,
+    inPoint: Option<f64>,
+    outPoint: Option<f64>,
+    gain: f64,
+    playback: Option<(Playback, u32)>, // video props: playback and the clip's sample rate
+    onStage: Option<Vec<Span>>,        // video props: heard only during these, if not the whole scene
+}
+
+/// Builds an ffmpeg filter graph mixing the scene's tracks, `scene.audio`, and the audio of
+/// any video props its frames use, while they're on stage, with the music bed ducked under them. Inputs are numbered
+/// from `firstInput`, after the video.
+/// The mix is padded or cut to the scene's length, or to frames `start..end` given a range.
+/// Returns None for a silent scene.
+pub fn mix(scene: &Scene, firstInput: usize, range: Option<(usize, usize)>) -> Result<Option<AudioMix>, String> {
+    let public = PathBuf::from(format!("{}/public", *PROJECT_DIR));
+    let mut sources: Vec<Source> = Vec::new();
+    if let Some(audio) = &scene.audio {
+        sources.push(Source {
+            path: public.join(audio),
+            offset: 0.0,
+            inPoint: None,
+            outPoint: None,
+            gain: 0.0,
+            playback: None,
+            onStage: None,
+        });
+    }
+    for track in scene.audioTracks.iter().flatten() {
+        sources.push(Source {
+            path: public.join(&track.source),
+            offset: track.offset.unwrap_or(0.0),
+            inPoint: track.inPoint,
+            outPoint: track.outPoint,
+            gain: track.gain.unwrap_or(0.0),
+            playback: None,
+            onStage: None,
+        });
+    }
+
+    // video props play their own audio while they're on stage; props inside precomputes don't,
+    // as their time is remapped per frame
+    let fps = scene.fps.max(1) as f64;
+    let duration = scene.frames.len() as f64 / fps;
+    let mut videoProps: Vec<_> = scene
+        .props
+        .values()
+        .filter(|p| p.propType == "video" && p.disabled != Some(true) && p.muted != Some(true))
+        .filter(|p| scene.frames.iter().any(|f| f.props.iter().any(|d| d.prop == p.id)))
+        .collect();
+    videoProps.sort_by(|a, b| a.id.cmp(&b.id));
+    for prop in videoProps {
+        let path = PathBuf::from(&prop.sprites[0]);
+        let Some(sampleRate) = audioSampleRate(&path)? else { continue };
+        let mut playback = prop.playback.clone().unwrap_or_default();
+        // aloop needs the loop's length, so a loop without an out point runs to the clip's end
+        // (a whole untrimmed loop is repeated at the input instead)
+        let needsEnd = match playback.loopMode.as_deref() {
+            Some("pingPong") => true,
+            Some("loop") => playback.inPoint.is_some(),
+            _ => false,
+        };
+        if needsEnd && playback.outPoint.is_none() {
+            playback.outPoint = Some(getVideoData(&prop.sprites[0])?.durationSec);
+        }
+
+        // runs of frames the prop is directed in
+        let mut spans: Vec<Span> = Vec::new();
+        for (i, frame) in scene.frames.iter().enumerate() {
+            if !frame.props.iter().any(|d| d.prop == prop.id) {
+                continue;
+            }
+            let (start, end) = (i as f64 / fps, (i + 1) as f64 / fps);
+            match spans.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => spans.push(Span { start, end }),
+            }
+        }
+        let wholeScene = spans.len() == 1 && spans[0].start == 0.0 && spans[0].end == duration;
+
+        sources.push(Source {
+            path,
+            offset: playback.startTime.unwrap_or(0.0),
+            inPoint: playback.inPoint,
+            outPoint: playback.outPoint,
+            gain: prop.audioGain.unwrap_or(0.0),
+            playback: Some((playback, sampleRate)),
+            onStage: if wholeScene { None } else { Some(spans) },
+        });
+    }
+    let dialogue = sources.len();
+    if let Some(music) = &scene.music {
+        let path = public.join(&music.source);
+        let sampleRate = audioSampleRate(&path)?.ok_or(format!("{} has no audio", music.source))?;
+        let loopMode = if music.r#loop == Some(true) { "loop" } else { "clamp" };
+        sources.push(Source {
+            path,
+            offset: music.offset.unwrap_or(0.0),
+            inPoint: music.inPoint,
+            outPoint: music.outPoint,
+            gain: music.gain.unwrap_or(0.0),
+            playback: Some((Playback { loopMode: Some(loopMode.into()), ..Default::default() }, sampleRate)),
+            onStage: None,
+        });
+    }
+    if sources.is_empty() {
+        return Ok(None);
+    }
+
+    let mut inputs: Vec<String> = Vec::new();
+    let mut chains: Vec<String> = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        // an untrimmed looping clip can just loop the whole file
+        let loopsWhole = source.inPoint.is_none()
+            && source.outPoint.is_none()
+            && source.playback.as_ref().is_some_and(|(p, _)| p.loopMode.as_deref() == Some("loop"));
+        if loopsWhole {
+            inputs.extend(["-stream_loop".into(), "-1".into()]);
+        }
+        inputs.extend(["-i".into(), source.path.to_string_lossy().into_owned()]);
+
+        let mut chain: Vec<String> = Vec::new();
+        if source.inPoint.is_some() || source.outPoint.is_some() {
+            let mut trim = format!("atrim=start={}", source.inPoint.unwrap_or(0.0));
+            if let Some(outPoint) = source.outPoint {
+                trim += &format!(":end={}", outPoint);
+            }
+            chain.push(trim);
+            chain.push("asetpts=PTS-STARTPTS".into());
+        }
+        if let Some((playback, sampleRate)) = &source.playback {
+            chain.extend(playbackFilters(playback, *sampleRate, source, i));
+        }
+        // nothing after the end of the scene is heard
+        chain.push(format!("atrim=end={}", (duration - source.offset).max(0.0)));
+        if source.gain != 0.0 {
+            chain.push(format!("volume={}dB", source.gain));
+        }
+        if source.offset > 0.0 {
+            chain.push(format!("adelay=delays={}:all=1", (source.offset * 1000.0).round() as u64));
+        }
+        // now in scene time: silent whenever the prop is off stage
+        if let Some(spans) = &source.onStage {
+            let onStage: Vec<String> = spans.iter().map(|s| format!("between(t,{},{})", s.start, s.end)).collect();
+            chain.push(format!("volume=0:enable='not({})'", onStage.join("+")));
+        }
+        chains.push(format!("[{}:a]{}[a{}]", firstInput + i, chain.join(","), i));
+    }
+
+    // mix at full level rather than averaging
+    let mut labels: Vec<String> = (0..sources.len()).map(|i| format!("[a{}]", i)).collect();
+    let ducking = scene.music.as_ref().and_then(|m| m.ducking.as_ref()).filter(|_| dialogue > 0);
+    if let Some(ducking) = ducking {
+        let music = labels.pop().unwrap_or_default();
+        let depth = ducking.depth.unwrap_or(12.0);
+        let attack = ducking.attack.unwrap_or(0.2);
+        let release = ducking.release.unwrap_or(0.8);
+        if ducking.mode.as_deref() == Some("sidechain") {
+            // the dialogue keys a compressor on the music; ratio is set so speech 20dB over the
+            // threshold cuts the music by roughly `depth`
+            let ratio = 20.0 / (20.0 - depth.clamp(0.0, 19.0));
+            chains.push(format!(
+                "{}amix=inputs={}:normalize=0,asplit[dialogue][key]",
+                labels.concat(),
+                dialogue
+            ));
+            chains.push(format!(
+                "{}[key]sidechaincompress=threshold={}dB:ratio={}:attack={}:release={}[ducked]",
+                music,
+                ducking.threshold.unwrap_or(-40.0),
+                ratio,
+                attack * 1000.0,
+                release * 1000.0
+            ));
+            labels = vec!["[dialogue]".into(), "[ducked]".into()];
+        } else {
+            // spans: multiply the music by a gain envelope rendered from the diarisation
+            let spans: Vec<Span> = ducking.spans.iter().flat_map(|s| s.values().flatten().copied()).collect();
+            let envelope = writeEnvelope(&spans, duration, depth, attack, release)?;
+            inputs.extend(["-i".into(), envelope.to_string_lossy().into_owned()]);
+            let format = "aformat=sample_fmts=flt:sample_rates=48000:channel_layouts=stereo";
+            chains.push(format!("{}{}[music]", music, format));
+            chains.push(format!("[{}:a]aresample=48000,{}[envelope]", firstInput + sources.len(), format));
+            chains.push("[music][envelope]amultiply[ducked]".into());
+            labels.push("[ducked]".into());
+        }
+    }
+
+    // then fit to the scene or range
+    let (start, end) = range.unwrap_or((0, scene.frames.len()));
+    chains.push(format!(
+        "{}amix=inputs={}:duration=longest:normalize=0,apad,atrim=start={}:end={},asetpts=PTS-STARTPTS[aout]",
+        labels.concat(),
+        labels.len(),
+        start as f64 / fps,
+        end as f64 / fps
+    ));
+    Ok(Some(AudioMix { inputs, filter: chains.join(";") }))
+}
+
+// reverse, loop and speed up a video prop's audio to match how its frames play
+fn playbackFilters(playback: &Playback, sampleRate: u32, source: &Source, index: usize) -> Vec<String> {
+    let mut filters: Vec<String> = Vec::new();
+    if playback.reverse == Some(true) {
+        filters.push("areverse".into());
+    }
+    if let Some(mode @ ("loop" | "pingPong")) = playback.loopMode.as_deref() {
+        if mode == "pingPong" {
+            filters.push(format!(
+                "asplit[f{0}][r{0}];[r{0}]areverse[b{0}];[f{0}][b{0}]concat=n=2:v=0:a=1",
+                index
+            ));
+        }
+        // aloop needs the loop length in samples; a loop over the whole file repeats at the input instead
+        if let Some(outPoint) = source.outPoint {
+            let start = source.inPoint.unwrap_or(0.0);
+            let factor = if mode == "pingPong" { 2.0 } else { 1.0 };
+            let size = ((outPoint - start) * factor * sampleRate as f64).round() as u64;
+            filters.push(format!("aloop=loop=-1:size={}", size));
+        }
+    }
+
+    // atempo only takes 0.5-2x, so bigger changes are chained
+    let mut rate = playback.rate.unwrap_or(1.0).abs();
+    if rate > 0.0 && rate != 1.0 {
+        while rate > 2.0 {
+            filters.push("atempo=2".into());
+            rate /= 2.0;
+        }
+        while rate < 0.5 {
+            filters.push("atempo=0.5".into());
+            rate /= 0.5;
+        }
+        filters.push(format!("atempo={}", rate));
+    }
+    filters
+}
+
+// Writes the music's gain over the scene as a mono WAV: 0dB, dropping by `depth` over `attack`
+// as each span of speech starts, and coming back over `release` once it ends.
+fn writeEnvelope(spans: &[Span], duration: f64, depth: f64, attack: f64, release: f64) -> Result<PathBuf, String> {
+    let key = cache::hashParts(&[format!("{:?}:{}:{}:{}:{}", spans, duration, depth, attack, release).as_bytes()]);
+    let path = TEMP_DIR.join(format!("duck_{}.wav", &key[..16]));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    // merge spans whose ramps would overlap, so at most one shapes the gain at any time
+    let mut spans = spans.to_vec();
+    spans.sort_by(|a, b| a.start.total_cmp(&b.start));
+    let mut merged: Vec<Span> = Vec::new();
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start - last.end < attack + release => last.end = last.end.max(span.end),
+            _ => merged.push(span),
+        }
+    }
+
+    let samples = (duration * ENVELOPE_RATE as f64).ceil() as usize;
+    let mut duck = vec![0.0f64; samples];
+    for span in merged {
+        let first = ((span.start - attack) * ENVELOPE_RATE as f64).floor().max(0.0) as usize;
+        let last = (((span.end + release) * ENVELOPE_RATE as f64).ceil() as usize).min(samples);
+        for (i, d) in duck.iter_mut().enumerate().take(last).skip(first) {
+            let t = i as f64 / ENVELOPE_RATE as f64;
+            let down = ((t - span.start + attack) / attack.max(1e-3)).clamp(0.0, 1.0);
+            let up = ((span.end + release - t) / release.max(1e-3)).clamp(0.0, 1.0);
+            *d = down * up;
+        }
+    }
+
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: ENVELOPE_RATE,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(&path, spec).map_err(|e| format!("failed to create wav: {}", e))?;
+    for d in duck {
+        writer
+            .write_sample(10f64.powf(-depth * d / 20.0) as f32)
+            .map_err(|e| format!("failed to write wav: {}", e))?;
+    }
+    writer.finalize().map_err(|e| format!("failed to write wav: {}", e))?;
+    Ok(path)
+}
+
+// sample rate of the file's first audio stream, or None if it has none
+fn audioSampleRate(path: &Path) -> Result<Option<u32>, String> {
+    let output = std::process::Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "a:0", "-show_entries", "stream=sample_rate", "-of", "csv=p=0"])
+        .arg(path)
+        .output()
+        .map_err(|e| format!("ffprobe failed: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ffprobe could not read {}", path.display()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(loopMode: &str, inPoint: Option<f64>, outPoint: Option<f64>) -> (Playback, Source) {
+        let playback = Playback { loopMode: Some(loopMode.into()), inPoint, outPoint, ..Default::default() };
+        let source = Source {
+            path: PathBuf::from("clip.mp4"),
+            offset: 0.0,
+            inPoint,
+            outPoint,
+            gain: 0.0,
+            playback: None,
+            onStage: None,
+        };
+        (playback, source)
+    }
+
+    #[test]
+    fn loopsUntrimmedPingPongAndOutPointOnlyClips() {
+        // mix() gives an untrimmed pingPong clip the clip's duration as its out point
+        let (playback, source) = clip("pingPong", None, Some(2.0));
+        let filters = playbackFilters(&playback, 48000, &source, 0);
+        assert!(filters.iter().any(|f| f == "aloop=loop=-1:size=192000"), "{:?}", filters);
+
+        let (playback, source) = clip("loop", None, Some(1.5));
+        let filters = playbackFilters(&playback, 48000, &source, 1);
+        assert!(filters.iter().any(|f| f == "aloop=loop=-1:size=72000"), "{:?}", filters);
+    }
+}
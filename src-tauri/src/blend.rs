@@ -0,0 +1,100 @@
+use image::RgbaImage;
+
+use super::cache::Sprite;
+
+/// Composites `src` onto `dest` at (`x`, `y`) with the given blend mode, scaling the
+/// source's alpha by `opacity`.
+///
+/// Modes are "overlay" (normal alpha blending), "add", "screen" and "multiply"; the
+/// result follows the W3C source-over compositing rules, so transparent areas of either
+/// image are respected.
+pub fn composite(dest: &mut RgbaImage, src: &Sprite, x: i64, y: i64, mode: &str, opacity: f32) {
+    let blendFn = blendFunction(mode);
+    let opacity = opacity.clamp(0.0, 1.0);
+
+    // clip to destination
+    let x0 = x.max(0);
+    let y0 = y.max(0);
+    let x1 = (x + src.width() as i64).min(dest.width() as i64);
+    let y1 = (y + src.height() as i64).min(dest.height() as i64);
+
+    for dy in y0..y1 {
+        for dx in x0..x1 {
+            let s = src.get_pixel((dx - x) as u32, (dy - y) as u32).0;
+            blendOver(&mut dest.get_pixel_mut(dx as u32, dy as u32).0, s, opacity, blendFn);
+        }
+    }
+}
+
+/// Fills a rectangle with `colour`, its alpha scaled by `alpha`, clipped to the canvas.
+/// Edges are rounded to whole pixels; an opaque fill just overwrites them.
+pub fn fillRect(canvas: &mut RgbaImage, x: f32, y: f32, width: f32, height: f32, colour: [u8; 4], alpha: f32) {
+    compositeRect(canvas, x, y, width, height, colour, "overlay", alpha);
+}
+
+/// Like `fillRect`, but blends the colour in with one of `composite`'s modes.
+#[allow(clippy::too_many_arguments)]
+pub fn compositeRect(canvas: &mut RgbaImage, x: f32, y: f32, width: f32, height: f32, colour: [u8; 4], mode: &str, alpha: f32) {
+    let clip = |v: f32, max: u32| v.round().clamp(0.0, max as f32) as u32;
+    let (x0, x1) = (clip(x, canvas.width()), clip(x + width, canvas.width()));
+    let (y0, y1) = (clip(y, canvas.height()), clip(y + height, canvas.height()));
+    if x0 >= x1 {
+        return;
+    }
+
+    if colour[3] == 255 && alpha >= 1.0 && matches!(mode, "paste" | "overlay") {
+        let canvasWidth = canvas.width() as usize;
+        let raw: &mut [u8] = canvas;
+        for row in y0..y1 {
+            let start = (row as usize * canvasWidth + x0 as usize) * 4;
+            let end = (row as usize * canvasWidth + x1 as usize) * 4;
+            for px in raw[start..end].chunks_exact_mut(4) {
+                px.copy_from_slice(&colour);
+            }
+        }
+        return;
+    }
+    let blendFn = blendFunction(mode);
+    for py in y0..y1 {
+        for px in x0..x1 {
+            blendOver(&mut canvas.get_pixel_mut(px, py).0, colour, alpha, blendFn);
+        }
+    }
+}
+
+/// Blends `colour` over one pixel, `coverage` scaling its alpha, e.g. for antialiased text.
+/// Pixels off the canvas are ignored.
+pub fn blendPixel(canvas: &mut RgbaImage, x: i64, y: i64, colour: [u8; 4], coverage: f32) {
+    if x < 0 || y < 0 || x >= canvas.width() as i64 || y >= canvas.height() as i64 {
+        return;
+    }
+    blendOver(&mut canvas.get_pixel_mut(x as u32, y as u32).0, colour, coverage, |_, s| s);
+}
+
+// per-channel blend for a mode; "paste" and "overlay" just take the source
+fn blendFunction(mode: &str) -> fn(f32, f32) -> f32 {
+    match mode {
+        "add" => |d, s| (d + s).min(1.0),
+        "screen" => |d, s| 1.0 - (1.0 - d) * (1.0 - s),
+        "multiply" => |d, s| d * s,
+        _ => |_, s| s,
+    }
+}
+
+// source-over with straight alpha, `opacity` scaling the source's alpha
+fn blendOver(d: &mut [u8; 4], s: [u8; 4], opacity: f32, blendFn: fn(f32, f32) -> f32) {
+    let sa = s[3] as f32 / 255.0 * opacity.clamp(0.0, 1.0);
+    if sa <= 0.0 {
+        return;
+    }
+    let da = d[3] as f32 / 255.0;
+
+    let outA = sa + da * (1.0 - sa);
+    for (c, sc) in s.iter().take(3).enumerate() {
+        let sc = *sc as f32 / 255.0;
+        let dc = d[c] as f32 / 255.0;
+        let co = sa * (1.0 - da) * sc + sa * da * blendFn(dc, sc) + (1.0 - sa) * da * dc;
+        d[c] = ((co / outA) * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    d[3] = (outA * 255.0).round() as u8;
+}
@@ -1,7 +1,56 @@
 use sha2::{Sha256, Digest};
 use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use directories::BaseDirs;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use memmap2::Mmap;
+
+// header of a binary asset cache entry; frame data follows immediately after,
+// so the file can be memory-mapped and each frame sliced out without decoding
+const ASSET_MAGIC: &[u8; 4] = b"SHAC";
+const ASSET_VERSION: u32 = 1;
+const ASSET_HEADER_SIZE: usize = 64;
+
+// bytes read from each end of a source file for its key, on top of its path, size and mtime
+const ASSET_SAMPLE_SIZE: u64 = 64 * 1024;
+
+// decoded frames are raw RGBA, several GB a minute at 1080p, so the asset cache is trimmed
+// back to this size after each write; STAGEHAND_ASSET_CACHE_GB overrides it
+const ASSET_CACHE_BUDGET_GB: f64 = 20.0;
+
+// makes temp names unique between writers in this process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Pixels of a sprite: decoded into memory, or a slice of a memory-mapped cache entry.
+#[derive(Clone, Debug)]
+pub enum FrameData {
+    Owned(Vec<u8>),
+    Mapped(Arc<Mmap>, usize, usize), // map, offset, length
+}
+
+impl Deref for FrameData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FrameData::Owned(data) => data,
+            FrameData::Mapped(mmap, offset, length) => &mmap[*offset..*offset + *length],
+        }
+    }
+}
+
+/// A decoded image that may be backed by the asset cache rather than owned.
+pub type Sprite = ImageBuffer<Rgba<u8>, FrameData>;
+
+pub fn ownedSprite(image: RgbaImage) -> Sprite {
+    let (width, height) = image.dimensions();
+    Sprite::from_raw(width, height, FrameData::Owned(image.into_raw())).expect("buffer matches its own dimensions")
+}
 
 fn cacheRoot() -> PathBuf {
     let base = BaseDirs::new().expect("no home dir");
@@ -12,6 +61,10 @@ fn cachePath(key: &str, kind: &str) -> PathBuf {
     cacheRoot().join(format!("{key}.{kind}.json"))
 }
 
+fn assetCachePath(key: &str) -> PathBuf {
+    cacheRoot().join("assets").join(format!("{key}.bin"))
+}
+
 pub fn readCache(key: &str, kind: &str) -> Option<String> {
     let path = cachePath(key, kind);
     fs::read_to_string(path).ok()
@@ -36,3 +89,191 @@ pub fn hashAudioFile(path: &Path, extra: &str) -> Result<String, String> {
     let result = hasher.finalize();
     Ok(hex::encode(result))
 }
+
+/// Hash of several byte strings, e.g. serialised parts of a scene for a render manifest.
+pub fn hashParts(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        // length-prefixed, so moving bytes between parts changes the hash
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Key for a decoded asset: the source file's canonical path, size, mtime and a sample of
+/// its first and last bytes, plus the size and pixel format it was decoded to. `extra`
+/// covers anything else that changes the decoded output (e.g. ffmpeg filters).
+///
+/// Sources can be multi-GB videos, so they aren't hashed whole; a file replaced in place
+/// changes its mtime, and usually its size and ends too.
+pub fn hashAssetFile(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixelFormat: &str,
+    extra: &str,
+) -> Result<String, String> {
+    let canonical = fs::canonicalize(path).map_err(|e| e.to_string())?;
+    let mut file = fs::File::open(&canonical).map_err(|e| e.to_string())?;
+    let metadata = file.metadata().map_err(|e| e.to_string())?;
+    let size = metadata.len();
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}:{size}:{mtime}", canonical.display()).as_bytes());
+    let mut sample = Vec::new();
+    (&mut file).take(ASSET_SAMPLE_SIZE).read_to_end(&mut sample).map_err(|e| e.to_string())?;
+    if size > ASSET_SAMPLE_SIZE * 2 {
+        file.seek(SeekFrom::End(-(ASSET_SAMPLE_SIZE as i64))).map_err(|e| e.to_string())?;
+        file.read_to_end(&mut sample).map_err(|e| e.to_string())?;
+    }
+    hasher.update(&sample);
+    hasher.update(format!("asset:{width}x{height}:{pixelFormat}:{extra}").as_bytes());
+
+    let result = hasher.finalize();
+    Ok(hex::encode(result))
+}
+
+/// Reads up to `maxFrames` decoded frames back from the asset cache.
+/// Returns `None` on a miss, or if the entry is truncated or stale. Frames are slices of
+/// the mapped entry, so pages are only read in as frames are drawn.
+pub fn readAssetCache(key: &str, maxFrames: Option<u64>) -> Option<Vec<Sprite>> {
+    let file = fs::File::open(assetCachePath(key)).ok()?;
+    // SAFETY: entries are only ever written to a temp file and renamed into place,
+    // so a mapped file is never modified underneath us
+    let mmap = Arc::new(unsafe { Mmap::map(&file) }.ok()?);
+
+    if mmap.len() < ASSET_HEADER_SIZE || &mmap[0..4] != ASSET_MAGIC {
+        return None;
+    }
+    let readU32 = |offset: usize| {
+        u32::from_le_bytes([mmap[offset], mmap[offset + 1], mmap[offset + 2], mmap[offset + 3]])
+    };
+    if readU32(4) != ASSET_VERSION {
+        return None;
+    }
+    let width = readU32(8);
+    let height = readU32(12);
+    let frameCount = readU32(16) as u64;
+    if frameCount == 0 {
+        return None;
+    }
+
+    let frameSize = (width as usize) * (height as usize) * 4;
+    if mmap.len() < ASSET_HEADER_SIZE + frameSize * frameCount as usize {
+        return None;
+    }
+
+    // the mtime marks the last use, for trimAssetCache
+    let _ = file.set_modified(SystemTime::now());
+
+    // only touch the pages we need, so stubbed previews of long clips stay cheap
+    let count = frameCount.min(maxFrames.unwrap_or(u64::MAX)) as usize;
+    let mut frames = Vec::with_capacity(count);
+    for i in 0..count {
+        let start = ASSET_HEADER_SIZE + i * frameSize;
+        let img = Sprite::from_raw(width, height, FrameData::Mapped(mmap.clone(), start, frameSize))?;
+        frames.push(img);
+    }
+    Some(frames)
+}
+
+/// Writes decoded frames to the asset cache, then trims it to its budget. All frames must
+/// share the same dimensions.
+pub fn writeAssetCache(key: &str, frames: &[RgbaImage]) -> Result<(), String> {
+    let (width, height) = frames.first().map(|f| f.dimensions()).unwrap_or((0, 0));
+    if frames.iter().any(|f| f.dimensions() != (width, height)) {
+        return Err(format!("cannot cache asset {key}: frames differ in size"));
+    }
+    let size = (ASSET_HEADER_SIZE + width as usize * height as usize * 4 * frames.len()) as u64;
+    let budget = assetCacheBudget();
+    if size > budget {
+        return Err(format!("cannot cache asset {key}: {size} bytes is over the cache budget of {budget}"));
+    }
+
+    let path = assetCachePath(key);
+    fs::create_dir_all(path.parent().unwrap())
+        .map_err(|e| e.to_string())?;
+
+    let mut header = [0u8; ASSET_HEADER_SIZE];
+    header[0..4].copy_from_slice(ASSET_MAGIC);
+    header[4..8].copy_from_slice(&ASSET_VERSION.to_le_bytes());
+    header[8..12].copy_from_slice(&width.to_le_bytes());
+    header[12..16].copy_from_slice(&height.to_le_bytes());
+    header[16..20].copy_from_slice(&(frames.len() as u32).to_le_bytes());
+
+    // write then rename, so readers never map a half-written entry
+    let tempPath = path.with_extension(format!(
+        "bin.{}-{}.tmp",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = fs::File::create(&tempPath).map_err(|e| e.to_string())?;
+    file.write_all(&header).map_err(|e| e.to_string())?;
+    for frame in frames {
+        file.write_all(frame.as_raw()).map_err(|e| e.to_string())?;
+    }
+    file.sync_all().map_err(|e| e.to_string())?;
+    drop(file);
+
+    fs::rename(&tempPath, &path).map_err(|e| e.to_string())?;
+    trimAssetCache(&path, budget)
+}
+
+/// Deletes every decoded asset, returning the bytes freed. Assets are decoded again as needed.
+pub fn clearAssetCache() -> Result<u64, String> {
+    let mut freed = 0;
+    for (_, size, path) in assetCacheEntries()? {
+        if fs::remove_file(&path).is_ok() {
+            freed += size;
+        }
+    }
+    Ok(freed)
+}
+
+fn assetCacheBudget() -> u64 {
+    let gb = std::env::var("STAGEHAND_ASSET_CACHE_GB")
+        .ok()
+        .and_then(|gb| gb.parse::<f64>().ok())
+        .unwrap_or(ASSET_CACHE_BUDGET_GB);
+    (gb * 1e9) as u64
+}
+
+// deletes the least recently used entries, other than `keep`, until the cache fits `budget`;
+// an entry still mapped by a render stays readable until it's unmapped (on Windows the
+// delete just fails, and it goes next time)
+fn trimAssetCache(keep: &Path, budget: u64) -> Result<(), String> {
+    let mut entries = assetCacheEntries()?;
+    let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+    entries.sort_by_key(|(used, _, _)| *used);
+    for (_, size, path) in entries {
+        if total <= budget {
+            break;
+        }
+        if path != keep && fs::remove_file(&path).is_ok() {
+            total -= size;
+        }
+    }
+    Ok(())
+}
+
+// last use, size and path of every file in the asset cache, including stray temp files
+fn assetCacheEntries() -> Result<Vec<(SystemTime, u64, PathBuf)>, String> {
+    let dir = cacheRoot().join("assets");
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(Vec::new());
+    };
+    Ok(entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect())
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use super::blend::{blendPixel, fillRect};
+use super::subtitles::{self, Analysis, Cue, SubtitleSettings};
+use super::PROJECT_DIR;
+
+/// Captions burned into the frame from `analyseAudio`'s transcript, with the word being
+/// spoken highlighted.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Captions {
+    pub analysis: Analysis,
+    pub grouping: Option<SubtitleSettings>, // how words are grouped into cues, and speaker names
+    pub font: String,                       // .ttf or .otf, relative to public/
+    pub size: Option<f32>,                  // px, default 1/18 of the canvas height
+    pub style: Option<CaptionStyle>,        // for every speaker
+    pub speakers: Option<HashMap<String, CaptionStyle>>, // per speaker name, over `style`
+    pub karaoke: Option<String>,            // "none" | "word" (default) | "fill"
+    pub fade: Option<f64>,                  // s to fade each cue in and out, default 0.1
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CaptionStyle {
+    pub colour: Option<[u8; 4]>,     // text RGBA, default white
+    pub highlight: Option<[u8; 4]>,  // the word being spoken, default yellow
+    pub background: Option<[u8; 4]>, // RGBA box behind the text, none if unset
+    pub padding: Option<f32>,        // px between the text and the edge of its box
+    pub x: Option<f32>,              // 0-1 across the canvas, to the centre of the text
+    pub y: Option<f32>,              // 0-1 down the canvas, to the bottom of the text
+}
+
+/// Captions ready to draw: the font loaded and the words grouped into cues.
+pub struct CaptionTrack {
+    font: FontVec,
+    size: f32,
+    captions: Captions,
+    cues: Vec<Cue>, // sorted by start
+}
+
+impl CaptionTrack {
+    pub fn load(captions: &Captions, canvasHeight: u32) -> Result<Self, String> {
+        let font = loadFont(&captions.font)?;
+
+        let mut cues = subtitles::groupCues(&captions.analysis, &captions.grouping.clone().unwrap_or_default());
+        cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+        Ok(CaptionTrack {
+            font,
+            size: captions.size.unwrap_or(canvasHeight as f32 / 18.0),
+            captions: captions.clone(),
+            cues,
+        })
+    }
+
+    // the style for a speaker: defaults, then the shared style, then the speaker's own
+    fn styleFor(&self, speaker: Option<&str>) -> CaptionStyle {
+        let layers = [
+            self.captions.style.as_ref(),
+            speaker.and_then(|s| self.captions.speakers.as_ref()?.get(s)),
+        ];
+        let mut style = CaptionStyle {
+            colour: Some([255, 255, 255, 255]),
+            highlight: Some([255, 220, 0, 255]),
+            background: None,
+            padding: Some(self.size * 0.3),
+            x: Some(0.5),
+            y: Some(0.92),
+        };
+        for layer in layers.into_iter().flatten() {
+            style.colour = layer.colour.or(style.colour);
+            style.highlight = layer.highlight.or(style.highlight);
+            style.background = layer.background.or(style.background);
+            style.padding = layer.padding.or(style.padding);
+            style.x = layer.x.or(style.x);
+            style.y = layer.y.or(style.y);
+        }
+        style
+    }
+
+    /// Draws the cue on screen at `time` (s), if any, over the canvas.
+    pub fn draw(&self, canvas: &mut RgbaImage, time: f64) {
+        let next = self.cues.partition_point(|c| c.start <= time);
+        let Some(cue) = next.checked_sub(1).map(|i| &self.cues[i]).filter(|c| time < c.end) else {
+            return;
+        };
+        let style = self.styleFor(cue.speaker.as_deref());
+        let fade = self.captions.fade.unwrap_or(0.1);
+        let alpha = if fade > 0.0 { ((time - cue.start).min(cue.end - time) / fade).clamp(0.0, 1.0) as f32 } else { 1.0 };
+
+        let font = self.font.as_scaled(PxScale::from(self.size));
+        let space = font.h_advance(font.glyph_id(' '));
+        let lineHeight = font.height() + font.line_gap();
+        let measure = |text: &str| measureText(&self.font, self.size, text);
+
+        // lines of (word, width), and the block they make
+        let lines: Vec<Vec<(&subtitles::Word, f32)>> = [&cue.words[..cue.split], &cue.words[cue.split..]]
+            .into_iter()
+            .filter(|words| !words.is_empty())
+            .map(|words| words.iter().map(|w| (w, measure(w.word.trim()))).collect())
+            .collect();
+        let lineWidth = |line: &Vec<(&subtitles::Word, f32)>| {
+            line.iter().map(|(_, w)| w).sum::<f32>() + space * line.len().saturating_sub(1) as f32
+        };
+        let blockWidth = lines.iter().map(lineWidth).fold(0.0, f32::max);
+        let blockHeight = lineHeight * lines.len() as f32;
+        let centreX = style.x.unwrap_or(0.5) * canvas.width() as f32;
+        let bottom = style.y.unwrap_or(0.92) * canvas.height() as f32;
+        let top = bottom - blockHeight;
+
+        if let Some(background) = style.background {
+            let padding = style.padding.unwrap_or(0.0);
+            fillRect(
+                canvas,
+                centreX - blockWidth / 2.0 - padding,
+                top - padding,
+                blockWidth + padding * 2.0,
+                blockHeight + padding * 2.0,
+                background,
+                alpha,
+            );
+        }
+
+        let karaoke = self.captions.karaoke.as_deref().unwrap_or("word");
+        for (row, line) in lines.iter().enumerate() {
+            let mut x = centreX - lineWidth(line) / 2.0;
+            let baseline = top + lineHeight * row as f32 + font.ascent();
+            for (word, width) in line {
+                let spoken = match karaoke {
+                    "word" => word.start <= time && time < word.end,
+                    "fill" => word.start <= time,
+                    _ => false,
+                };
+                let colour = if spoken { style.highlight } else { style.colour }.unwrap_or([255; 4]);
+                drawText(canvas, &self.font, self.size, word.word.trim(), x, baseline, colour, alpha);
+                x += width + space;
+            }
+        }
+    }
+}
+
+/// A line or two of text over a still, e.g. a thumbnail's title.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Title {
+    pub text: String,                // "\n" starts a new line
+    pub font: String,                // .ttf or .otf, relative to public/
+    pub size: Option<f32>,           // px, default 1/10 of the image height
+    pub style: Option<CaptionStyle>, // `highlight` is unused
+}
+
+/// Draws `title` over the canvas, laid out like a caption cue.
+pub fn drawTitle(canvas: &mut RgbaImage, title: &Title) -> Result<(), String> {
+    let font = loadFont(&title.font)?;
+    let size = title.size.unwrap_or(canvas.height() as f32 / 10.0);
+    let style = title.style.clone().unwrap_or_default();
+    let scaled = font.as_scaled(PxScale::from(size));
+    let lineHeight = scaled.height() + scaled.line_gap();
+
+    let lines: Vec<(&str, f32)> = title.text.lines().map(|l| (l.trim(), measureText(&font, size, l.trim()))).collect();
+    let blockWidth = lines.iter().map(|(_, w)| *w).fold(0.0, f32::max);
+    let blockHeight = lineHeight * lines.len() as f32;
+    let centreX = style.x.unwrap_or(0.5) * canvas.width() as f32;
+    let bottom = style.y.unwrap_or(0.92) * canvas.height() as f32;
+    let top = bottom - blockHeight;
+
+    if let Some(background) = style.background {
+        let padding = style.padding.unwrap_or(size * 0.3);
+        fillRect(
+            canvas,
+            centreX - blockWidth / 2.0 - padding,
+            top - padding,
+            blockWidth + padding * 2.0,
+            blockHeight + padding * 2.0,
+            background,
+            1.0,
+        );
+    }
+    for (row, (line, width)) in lines.iter().enumerate() {
+        let baseline = top + lineHeight * row as f32 + scaled.ascent();
+        let colour = style.colour.unwrap_or([255; 4]);
+        drawText(canvas, &font, size, line, centreX - width / 2.0, baseline, colour, 1.0);
+    }
+    Ok(())
+}
+
+fn loadFont(font: &str) -> Result<FontVec, String> {
+    let path = format!("{}/public/{}", *PROJECT_DIR, font);
+    let data = std::fs::read(&path).map_err(|e| format!("failed to read font {}: {}", path, e))?;
+    FontVec::try_from_vec(data).map_err(|e| format!("invalid font {}: {}", path, e))
+}
+
+// the advance width of `text`, kerning included
+fn measureText(font: &FontVec, size: f32, text: &str) -> f32 {
+    let font = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+#[allow(clippy::too_many_arguments)]
+fn drawText(canvas: &mut RgbaImage, font: &FontVec, size: f32, text: &str, x: f32, baseline: f32, colour: [u8; 4], alpha: f32) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut caret = x;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(size, point(caret, baseline));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+
+        let Some(outline) = font.outline_glyph(glyph) else { continue };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + gx as i64;
+            let py = bounds.min.y as i64 + gy as i64;
+            blendPixel(canvas, px, py, colour, coverage * alpha);
+        });
+    }
+}
@@ -0,0 +1,175 @@
+use image::{DynamicImage, ImageDecoder, ImageReader, RgbaImage};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+
+pub const DEFAULT_COLOUR_SPACE: &str = "srgb";
+
+// 4x4 Bayer matrix, used to dither 16-bit assets down to the 8-bit canvas
+const BAYER: [[u16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+fn profileFor(colourSpace: &str) -> Result<ColorProfile, String> {
+    match colourSpace {
+        "srgb" => Ok(ColorProfile::new_srgb()),
+        "displayP3" => Ok(ColorProfile::new_display_p3()),
+        "bt2020" => Ok(ColorProfile::new_bt2020()),
+        _ => Err(format!("unsupported colour space: {}", colourSpace)),
+    }
+}
+
+/// Opens an image and converts it into the scene's working colour space.
+///
+/// Embedded ICC profiles are honoured (untagged images are assumed to be sRGB), and
+/// 16-bit sources are converted at full depth then dithered to 8 bits, to keep gradients smooth.
+pub fn openImage(path: &str, colourSpace: &str) -> Result<RgbaImage, String> {
+    let mut decoder = ImageReader::open(path)
+        .map_err(|e| e.to_string())?
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_decoder()
+        .map_err(|e| e.to_string())?;
+    let icc = decoder.icc_profile().map_err(|e| e.to_string())?;
+    let img = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    let highDepth = img.color().bytes_per_pixel() / img.color().channel_count() > 1;
+
+    let source = match icc {
+        Some(icc) => match ColorProfile::new_from_slice(&icc) {
+            Ok(profile) if profile.color_space == DataColorSpace::Rgb => Some(profile),
+            Ok(_) => None, // grey/CMYK profiles: let `image` handle the conversion to RGBA
+            Err(e) => {
+                println!("ignoring invalid ICC profile in {}: {:?}", path, e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // nothing to convert
+    if source.is_none() && colourSpace == DEFAULT_COLOUR_SPACE {
+        return Ok(if highDepth { dither(&img.to_rgba16()) } else { img.to_rgba8() });
+    }
+
+    let source = source.unwrap_or_else(ColorProfile::new_srgb);
+    let target = profileFor(colourSpace)?;
+    let transform = source
+        .create_transform_16bit(Layout::Rgba, &target, Layout::Rgba, TransformOptions::default())
+        .map_err(|e| format!("failed to build colour transform for {}: {:?}", path, e))?;
+
+    let src = img.to_rgba16();
+    let mut dst = image::ImageBuffer::<image::Rgba<u16>, Vec<u16>>::new(src.width(), src.height());
+    transform
+        .transform(src.as_raw(), &mut dst)
+        .map_err(|e| format!("failed to convert colour space of {}: {:?}", path, e))?;
+
+    Ok(if highDepth { dither(&dst) } else { quantise(&dst) })
+}
+
+fn quantise(img: &image::ImageBuffer<image::Rgba<u16>, Vec<u16>>) -> RgbaImage {
+    let raw = img.as_raw().iter().map(|v| ((*v as u32 + 128) / 257) as u8).collect();
+    RgbaImage::from_raw(img.width(), img.height(), raw).unwrap()
+}
+
+fn dither(img: &image::ImageBuffer<image::Rgba<u16>, Vec<u16>>) -> RgbaImage {
+    let mut out = RgbaImage::new(img.width(), img.height());
+    for (x, y, px) in img.enumerate_pixels() {
+        // spread the threshold across one 8-bit step (257 in 16-bit)
+        let threshold = (BAYER[(y % 4) as usize][(x % 4) as usize] as u32 * 257) / 16;
+        let mut rgba = [0u8; 4];
+        for (c, value) in px.0.iter().enumerate() {
+            rgba[c] = ((*value as u32 + threshold) / 257).min(255) as u8;
+        }
+        out.put_pixel(x, y, image::Rgba(rgba));
+    }
+    out
+}
+
+/// Lookup table from 8-bit channel values to linear light, for formats like EXR that
+/// expect scene-linear data.
+pub fn linearTable(colourSpace: &str) -> Result<[f32; 256], String> {
+    let (_, transfer, _) = ffmpegColourTags(colourSpace)?;
+    let mut table = [0.0; 256];
+    for (i, out) in table.iter_mut().enumerate() {
+        let v = i as f32 / 255.0;
+        *out = if transfer == "iec61966-2-1" {
+            if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+        } else if v < 0.081 {
+            v / 4.5
+        } else {
+            ((v + 0.099) / 1.099).powf(1.0 / 0.45)
+        };
+    }
+    Ok(table)
+}
+
+/// ffmpeg's names for a working colour space: (primaries, transfer, matrix).
+pub fn ffmpegColourTags(colourSpace: &str) -> Result<(&'static str, &'static str, &'static str), String> {
+    match colourSpace {
+        "srgb" => Ok(("bt709", "iec61966-2-1", "bt709")),
+        "displayP3" => Ok(("smpte432", "iec61966-2-1", "bt709")),
+        "bt2020" => Ok(("bt2020", "bt2020-10", "bt2020nc")),
+        _ => Err(format!("colour space {} cannot be encoded to video", colourSpace)),
+    }
+}
+
+/// Output arguments so ffmpeg converts RGB to YUV with the right matrix, and tags the stream.
+/// RGB outputs (e.g. PNG) need no conversion, and are only tagged with primaries and transfer.
+pub fn ffmpegOutputArgs(colourSpace: &str, rgb: bool) -> Result<Vec<String>, String> {
+    let (primaries, transfer, matrix) = ffmpegColourTags(colourSpace)?;
+    let tags = vec![
+        "-color_primaries".into(),
+        primaries.into(),
+        "-color_trc".into(),
+        transfer.into(),
+    ];
+    if rgb {
+        return Ok(tags);
+    }
+
+    // swscale takes "bt2020" for the non-constant luminance matrix
+    let scaleMatrix = if matrix == "bt2020nc" { "bt2020" } else { matrix };
+    let mut args = vec![
+        "-vf".into(),
+        format!("scale=out_color_matrix={}:out_range=tv", scaleMatrix),
+    ];
+    args.extend(tags);
+    args.extend([
+        "-colorspace".into(),
+        matrix.into(),
+        "-color_range".into(),
+        "tv".into(),
+    ]);
+    Ok(args)
+}
+
+/// Filter converting decoded video into the working colour space, unless its primaries and
+/// transfer already match. `source` is the footage's own space: "bt709" | "bt601" | "bt2020" |
+/// "srgb" | "displayP3". It is given to the filter outright, as untagged footage (most phone
+/// and screen recordings) would otherwise make it fail.
+pub fn ffmpegInputFilter(colourSpace: &str, source: &str) -> Result<Option<String>, String> {
+    let (primaries, transfer, matrix) = ffmpegColourTags(colourSpace)?;
+    let space = if matrix == "bt2020nc" { "bt2020ncl" } else { matrix };
+    // (matrix, primaries, transfer) of the source
+    let (ispace, iprimaries, itrc) = match source {
+        "bt709" => ("bt709", "bt709", "bt709"),
+        "bt601" => ("smpte170m", "smpte170m", "smpte170m"),
+        "bt2020" => ("bt2020ncl", "bt2020", "bt2020-10"),
+        "srgb" => ("bt709", "bt709", "iec61966-2-1"),
+        "displayP3" => ("bt709", "smpte432", "iec61966-2-1"),
+        _ => return Err(format!("unknown source colour space: {}", source)),
+    };
+    if (iprimaries, itrc) == (primaries, transfer) {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "colorspace=space={}:primaries={}:trc={}:ispace={}:iprimaries={}:itrc={}",
+        space, primaries, transfer, ispace, iprimaries, itrc
+    )))
+}
+
+/// The source colour space implied by a stream's primaries tag, bt709 if it has none.
+pub fn sourceColourSpace(primaries: &str) -> &'static str {
+    match primaries {
+        "bt2020" => "bt2020",
+        "smpte432" => "displayP3",
+        "smpte170m" | "bt470bg" => "bt601",
+        _ => "bt709",
+    }
+}
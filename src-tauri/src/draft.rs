@@ -0,0 +1,118 @@
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use super::{blend, Prop, Scene};
+
+// 5x7 bitmaps for the watermark, a row per byte, most significant of the low 5 bits leftmost
+const GLYPHS: [[u8; 7]; 5] = [
+    [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110], // D
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001], // R
+    [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001], // A
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000], // F
+    [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100], // T
+];
+
+/// A quick, low-quality render for checking timing.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct DraftSettings {
+    pub scale: Option<f64>,      // fraction of the full resolution, 0-1, default 0.5
+    pub fps: Option<u32>,        // drop frames down to at most this rate
+    pub watermark: Option<bool>, // stamp DRAFT on every frame, default true
+}
+
+/// Shrinks a scene for a draft: the canvas, props and positions by `scale`, with every
+/// `step`th frame kept for a lower frame rate. Returns the draft and the step, so frame
+/// numbers can be mapped across. The draft is renamed `{id}_draft`, so its output, segments
+/// and image sequences never mix with the full render's, and is marked for the watermark.
+pub fn draftScene(mut scene: Scene, settings: &DraftSettings) -> Result<(Scene, usize), String> {
+    let scale = settings.scale.unwrap_or(0.5);
+    if !(scale > 0.0 && scale <= 1.0) {
+        return Err(format!("draft scale must be in 0-1, not {}", scale));
+    }
+
+    scaleScene(&mut scene, scale);
+    // encoders want even dimensions for 4:2:0 chroma
+    let even = |v: u32| (v / 2 * 2).max(2);
+    scene.canvasSize.width = even(scene.canvasSize.width);
+    scene.canvasSize.height = even(scene.canvasSize.height);
+
+    // the smallest step that gets down to the rate, keeping a whole number of frames per second
+    let step = match settings.fps {
+        Some(fps) if fps > 0 && fps < scene.fps => (1..=scene.fps)
+            .find(|step| scene.fps.is_multiple_of(*step) && scene.fps / step <= fps)
+            .unwrap_or(1) as usize,
+        _ => 1,
+    };
+    if step > 1 {
+        scene.frames = scene.frames.into_iter().step_by(step).collect();
+        scene.fps /= step as u32;
+    }
+
+    scene.id = format!("{}_draft", scene.id);
+    scene.watermark = Some(settings.watermark.unwrap_or(true));
+    Ok((scene, step))
+}
+
+// scales everything measured in canvas pixels, through the precompute tree
+fn scaleScene(scene: &mut Scene, scale: f64) {
+    let px = |v: u32| (v as f64 * scale).round() as u32;
+    let size = |v: u32| px(v).max(1);
+
+    scene.canvasSize.width = size(scene.canvasSize.width);
+    scene.canvasSize.height = size(scene.canvasSize.height);
+    for prop in scene.props.values_mut() {
+        scaleProp(prop, scale);
+    }
+    for direction in scene.frames.iter_mut().flat_map(|f| f.props.iter_mut()) {
+        direction.x = px(direction.x);
+        direction.y = px(direction.y);
+        direction.width = direction.width.map(size);
+        direction.height = direction.height.map(size);
+    }
+    if let Some(captions) = &mut scene.captions {
+        captions.size = captions.size.map(|s| s * scale as f32);
+        for style in captions.style.iter_mut().chain(captions.speakers.iter_mut().flat_map(|s| s.values_mut())) {
+            style.padding = style.padding.map(|p| p * scale as f32);
+        }
+    }
+    for precompute in scene.precompute.iter_mut() {
+        scaleScene(precompute, scale);
+    }
+}
+
+fn scaleProp(prop: &mut Prop, scale: f64) {
+    let size = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+    match prop.propType.as_str() {
+        // always drawn at width x height, 1920x1080 if unset
+        "video" | "colour" => {
+            prop.width = Some(size(prop.width.unwrap_or(1920)));
+            prop.height = Some(size(prop.height.unwrap_or(1080)));
+        }
+        // fitted sprites come out at width x height; the rest keep their own size, so are resampled
+        _ => match (&prop.fit, prop.width, prop.height) {
+            (Some(_), Some(width), Some(height)) => {
+                prop.width = Some(size(width));
+                prop.height = Some(size(height));
+            }
+            _ => prop.scale = Some(prop.scale.unwrap_or(1.0) * scale as f32),
+        },
+    }
+}
+
+/// Stamps DRAFT in the top left corner of a frame.
+pub fn watermark(canvas: &mut RgbaImage) {
+    let cell = (canvas.height() as f32 / 60.0).max(1.0);
+    let margin = cell * 3.0;
+    // a shadow under the whole word first, so it reads over light and dark frames alike
+    for (offset, colour, alpha) in [(cell / 2.0, [0, 0, 0, 255], 0.6), (0.0, [255, 255, 255, 255], 0.8)] {
+        for (i, glyph) in GLYPHS.iter().enumerate() {
+            let left = margin + i as f32 * cell * 6.0;
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in (0..5).filter(|column| bits & (0b10000 >> column) != 0) {
+                    let (x, y) = (left + column as f32 * cell, margin + row as f32 * cell);
+                    blend::fillRect(canvas, x + offset, y + offset, cell, cell, colour, alpha);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::draft::DraftSettings;
+use super::metadata::Metadata;
+use super::PROJECT_DIR;
+
+/// Encoder settings for `renderVideo`. Anything left unset comes from the named
+/// `target` preset, then from the codec's defaults.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct RenderSettings {
+    pub target: Option<String>,       // "youtube" | "vimeo" | "instagram" | "tiktok" | "web" | "master" | "webAlpha" | "masterAlpha"
+    pub codec: Option<String>,        // "h264" | "h265" | "vp9" | "av1" | "prores" | "png"
+    pub crf: Option<u32>,
+    pub bitrate: Option<String>,      // e.g. "8M"; takes priority over crf
+    pub preset: Option<String>,       // encoder speed preset; ProRes profile for "prores"
+    pub pixelFormat: Option<String>,
+    pub gopSize: Option<u32>,         // frames
+    pub audioCodec: Option<String>,   // "aac" | "opus" | "mp3" | "flac" | "pcm"
+    pub audioBitrate: Option<String>, // e.g. "192k"
+    pub container: Option<String>,    // "mp4" | "mov" | "webm" | "mkv"
+    pub alpha: Option<bool>,          // keep the canvas's transparency; ProRes 4444, VP9 or PNG only
+    pub sequence: Option<SequenceSettings>, // write numbered images instead of a video file
+    pub start: Option<usize>,         // first frame to render, inclusive
+    pub end: Option<usize>,           // last frame to render, exclusive
+    pub segmentFrames: Option<usize>, // encode as segments of this many frames, joined at the end
+    pub output: Option<String>,       // path template, relative to bin/; see output::resolveOutputPath
+    pub overwrite: Option<String>,    // "fail" | "version" | "overwrite"
+    pub subtitles: Option<Vec<SubtitleTrack>>, // soft subtitle tracks, e.g. from exportSubtitles
+    pub draft: Option<DraftSettings>, // render small and fast, to check timing
+    pub metadata: Option<Metadata>,   // container tags and chapters, over the scene's
+}
+
+/// A subtitle file muxed into the output as its own track.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SubtitleTrack {
+    pub file: String,             // .srt, .vtt or .ass, relative to bin/
+    pub language: Option<String>, // ISO 639-2, e.g. "eng"
+}
+
+/// Image sequence output, for handing renders to compositing software.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SequenceSettings {
+    pub format: Option<String>,     // "png" | "tiff" | "exr"
+    pub bitDepth: Option<u8>,       // 8 | 16 for png and tiff; exr is always 32-bit float
+    pub startNumber: Option<usize>, // number of the scene's first frame
+    pub padding: Option<usize>,     // digits in the frame number
+    pub prefix: Option<String>,     // defaults to the scene id
+}
+
+/// Fully resolved settings, ready to hand to ffmpeg.
+#[derive(Clone, Debug)]
+pub struct EncoderSettings {
+    pub codec: String,
+    pub encoder: String,
+    pub crf: Option<u32>,
+    pub bitrate: Option<String>,
+    pub preset: Option<String>,
+    pub pixelFormat: String,
+    pub gopSize: Option<u32>,
+    pub audioCodec: String,
+    pub audioEncoder: String,
+    pub audioBitrate: Option<String>,
+    pub container: String,
+    pub alpha: bool,
+    pub subtitles: Vec<SubtitleTrack>, // files resolved to absolute paths
+    pub metadata: Option<Metadata>,    // merged over the scene's when muxing
+}
+
+// named presets for our upload targets; GOP sizes are in seconds, converted using the scene fps
+fn targetPreset(target: &str, fps: u32) -> Result<RenderSettings, String> {
+    let gop = |seconds: f64| Some(((fps as f64 * seconds).round() as u32).max(1));
+    let settings = |codec: &str, crf, preset: &str, gopSize, audioCodec: &str, audioBitrate: Option<&str>, container: &str| {
+        RenderSettings {
+            target: None,
+            codec: Some(codec.into()),
+            crf,
+            bitrate: None,
+            preset: Some(preset.into()),
+            pixelFormat: None,
+            gopSize,
+            audioCodec: Some(audioCodec.into()),
+            audioBitrate: audioBitrate.map(|b| b.into()),
+            container: Some(container.into()),
+            alpha: None,
+            sequence: None,
+            start: None,
+            end: None,
+            segmentFrames: None,
+            output: None,
+            overwrite: None,
+            subtitles: None,
+            draft: None,
+            metadata: None,
+        }
+    };
+    let withAlpha = |mut settings: RenderSettings| {
+        settings.alpha = Some(true);
+        settings
+    };
+    match target {
+        "youtube" => Ok(settings("h264", Some(18), "slow", gop(0.5), "aac", Some("384k"), "mp4")),
+        "vimeo" => Ok(settings("h264", Some(18), "slow", gop(1.0), "aac", Some("320k"), "mp4")),
+        "instagram" => Ok(settings("h264", Some(21), "medium", gop(1.0), "aac", Some("128k"), "mp4")),
+        "tiktok" => Ok(settings("h264", Some(20), "medium", gop(1.0), "aac", Some("192k"), "mp4")),
+        "web" => Ok(settings("vp9", Some(32), "2", gop(2.0), "opus", Some("128k"), "webm")),
+        "master" => Ok(settings("prores", None, "hq", None, "pcm", None, "mov")),
+        "webAlpha" => Ok(withAlpha(settings("vp9", Some(32), "2", gop(2.0), "opus", Some("128k"), "webm"))),
+        "masterAlpha" => Ok(withAlpha(settings("prores", None, "4444", None, "pcm", None, "mov"))),
+        _ => Err(format!("unknown render target: {}", target)),
+    }
+}
+
+// the quickest encode each codec offers, for drafts
+fn fastestPreset(codec: &str, alpha: bool) -> Option<String> {
+    match codec {
+        "h264" | "h265" => Some("ultrafast".into()),
+        "vp9" => Some("8".into()),
+        "av1" => Some("12".into()),
+        // Proxy has no alpha; 4444 is the only profile that does
+        "prores" if !alpha => Some("proxy".into()),
+        _ => None,
+    }
+}
+
+/// Settings for `renderAnimation`: a short clip as an animated GIF or WebP, for chat platforms.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct AnimationSettings {
+    pub format: Option<String>, // "gif" | "webp"
+    pub start: Option<usize>,   // first frame, inclusive
+    pub end: Option<usize>,     // last frame, exclusive
+    pub width: Option<u32>,     // scale down to this width, keeping the aspect ratio
+    pub fps: Option<u32>,       // drop frames down to this rate
+    pub colours: Option<u32>,   // gif palette size, 2-256
+    pub dither: Option<String>, // gif only: "none" | "bayer" | "floydSteinberg" | "sierra"
+    pub dedup: Option<bool>,    // merge identical consecutive frames into one longer frame
+    pub quality: Option<u32>,   // webp quality, 0-100
+    pub maxBytes: Option<u64>,  // cut colours or quality, then size, until the file fits
+    pub output: Option<String>, // path template, as RenderSettings.output
+    pub overwrite: Option<String>, // as RenderSettings.overwrite
+}
+
+/// Fills in the defaults for an animation, and checks the installed ffmpeg can encode it.
+pub fn resolveAnimation(settings: &AnimationSettings) -> Result<AnimationSettings, String> {
+    let format = settings.format.clone().unwrap_or("gif".into());
+    let encoder = match format.as_str() {
+        "gif" => "gif",
+        "webp" => "libwebp_anim",
+        _ => return Err(format!("unsupported animation format: {}", format)),
+    };
+    if !ffmpegList(&["-encoders"])?.contains(encoder) {
+        return Err(format!("installed ffmpeg has no {} encoder", encoder));
+    }
+
+    let colours = settings.colours.unwrap_or(256);
+    if !(2..=256).contains(&colours) {
+        return Err(format!("gif palettes hold 2-256 colours, not {}", colours));
+    }
+    let quality = settings.quality.unwrap_or(75);
+    if quality > 100 {
+        return Err(format!("webp quality must be 0-100, not {}", quality));
+    }
+    let dither = settings.dither.clone().unwrap_or("sierra".into());
+    if !["none", "bayer", "floydSteinberg", "sierra"].contains(&dither.as_str()) {
+        return Err(format!("unsupported dither: {}", dither));
+    }
+    if let (Some(start), Some(end)) = (settings.start, settings.end) {
+        if end <= start {
+            return Err(format!("empty frame range {}..{}", start, end));
+        }
+    }
+
+    Ok(AnimationSettings {
+        format: Some(format),
+        start: Some(settings.start.unwrap_or(0)),
+        end: settings.end,
+        width: settings.width,
+        fps: settings.fps,
+        colours: Some(colours),
+        dither: Some(dither),
+        dedup: Some(settings.dedup.unwrap_or(true)),
+        quality: Some(quality),
+        maxBytes: settings.maxBytes,
+        output: settings.output.clone(),
+        overwrite: Some(settings.overwrite.clone().unwrap_or("version".into())),
+    })
+}
+
+/// ffmpeg output arguments for one attempt at an animation; `colours`, `quality` and
+/// `width` may be cut below the settings' own to hit `maxBytes`.
+pub fn animationArgs(settings: &AnimationSettings, colours: u32, quality: u32, width: u32) -> Vec<String> {
+    let mut filter = String::new();
+    if let Some(fps) = settings.fps {
+        filter += &format!("fps={},", fps);
+    }
+    if settings.dedup == Some(true) {
+        filter += "mpdecimate,";
+    }
+    filter += &format!("scale={}:-1:flags=lanczos", width);
+
+    let mut args: Vec<String> = Vec::new();
+    if settings.format.as_deref() == Some("webp") {
+        args.extend([
+            "-vf".into(),
+            filter,
+            "-c:v".into(),
+            "libwebp_anim".into(),
+            "-quality".into(),
+            quality.to_string(),
+            "-pix_fmt".into(),
+            "yuva420p".into(),
+            "-f".into(),
+            "webp".into(),
+        ]);
+    } else {
+        let dither = match settings.dither.as_deref() {
+            Some("none") => "none",
+            Some("bayer") => "bayer:bayer_scale=3",
+            Some("floydSteinberg") => "floyd_steinberg",
+            _ => "sierra2_4a",
+        };
+        // one palette for the whole clip, weighted towards what changes between frames
+        filter += &format!(
+            ",split[a][b];[a]palettegen=max_colors={}:reserve_transparent=1:stats_mode=diff[p];\
+             [b][p]paletteuse=dither={}:diff_mode=rectangle:alpha_threshold=128",
+            colours, dither
+        );
+        args.extend(["-vf".into(), filter, "-f".into(), "gif".into()]);
+    }
+    // deduped frames are held on screen, rather than the clip speeding up
+    if settings.dedup == Some(true) {
+        args.extend(["-fps_mode".into(), "vfr".into()]);
+    }
+    args.extend(["-loop".into(), "0".into()]);
+    args
+}
+
+/// Fills in the defaults for an image sequence, and checks the format can hold the bit depth.
+pub fn resolveSequence(settings: &SequenceSettings) -> Result<SequenceSettings, String> {
+    let format = settings.format.clone().unwrap_or("png".into());
+    let bitDepth = match (format.as_str(), settings.bitDepth) {
+        ("png" | "tiff", None) => 8,
+        ("png" | "tiff", Some(depth @ (8 | 16))) => depth,
+        ("exr", None | Some(32)) => 32,
+        ("png" | "tiff" | "exr", Some(depth)) => return Err(format!("{} cannot hold {}-bit frames", format, depth)),
+        _ => return Err(format!("unsupported image sequence format: {}", format)),
+    };
+    Ok(SequenceSettings {
+        format: Some(format),
+        bitDepth: Some(bitDepth),
+        startNumber: Some(settings.startNumber.unwrap_or(0)),
+        padding: Some(settings.padding.unwrap_or(5)),
+        prefix: settings.prefix.clone(),
+    })
+}
+
+/// Merges `settings` over its target preset and the codec defaults.
+pub fn resolve(settings: &RenderSettings, fps: u32) -> Result<EncoderSettings, String> {
+    let base = match &settings.target {
+        Some(target) => targetPreset(target, fps)?,
+        None => RenderSettings::default(),
+    };
+    let pick = |a: &Option<String>, b: &Option<String>| a.clone().or(b.clone());
+
+    let alpha = settings.alpha.or(base.alpha).unwrap_or(false);
+    let codec = pick(&settings.codec, &base.codec).unwrap_or(if alpha { "prores".into() } else { "h264".into() });
+    let (encoder, defaultPixelFormat, defaultContainer) = match (codec.as_str(), alpha) {
+        ("h264", false) => ("libx264", "yuv420p", "mp4"),
+        ("h265", false) => ("libx265", "yuv420p", "mp4"),
+        ("vp9", false) => ("libvpx-vp9", "yuv420p", "webm"),
+        ("vp9", true) => ("libvpx-vp9", "yuva420p", "webm"),
+        ("av1", false) => ("libsvtav1", "yuv420p", "mp4"),
+        ("prores", false) => ("prores_ks", "yuv422p10le", "mov"),
+        ("prores", true) => ("prores_ks", "yuva444p10le", "mov"),
+        ("png", _) => ("png", if alpha { "rgba" } else { "rgb24" }, "mov"),
+        ("h264" | "h265" | "av1", true) => {
+            return Err(format!("{} cannot carry an alpha channel, use prores, vp9 or png", codec))
+        }
+        _ => return Err(format!("unsupported codec: {}", codec)),
+    };
+
+    let container = pick(&settings.container, &base.container).unwrap_or(defaultContainer.into());
+    let audioCodec = pick(&settings.audioCodec, &base.audioCodec).unwrap_or(match container.as_str() {
+        "webm" => "opus".into(),
+        _ => "aac".into(),
+    });
+    let audioEncoder = match audioCodec.as_str() {
+        "aac" => "aac",
+        "opus" => "libopus",
+        "mp3" => "libmp3lame",
+        "flac" => "flac",
+        "pcm" => "pcm_s16le",
+        _ => return Err(format!("unsupported audio codec: {}", audioCodec)),
+    };
+
+    let bitrate = pick(&settings.bitrate, &base.bitrate);
+    let crf = if bitrate.is_some() || codec == "prores" || codec == "png" {
+        None
+    }
+    else {
+        settings.crf.or(base.crf).or(match codec.as_str() {
+            "h264" => Some(20),
+            "h265" => Some(24),
+            "vp9" => Some(32),
+            "av1" => Some(30),
+            _ => None,
+        })
+    };
+
+    Ok(EncoderSettings {
+        encoder: encoder.into(),
+        crf,
+        bitrate,
+        // ProRes only keeps alpha in its 4444 profiles
+        preset: match (&settings.preset, &settings.draft) {
+            (Some(preset), _) => Some(preset.clone()),
+            (None, Some(_)) => fastestPreset(&codec, alpha),
+            (None, None) => base.preset.clone(),
+        }
+        .or(if alpha && codec == "prores" { Some("4444".into()) } else { None }),
+        pixelFormat: pick(&settings.pixelFormat, &base.pixelFormat).unwrap_or(defaultPixelFormat.into()),
+        gopSize: settings.gopSize.or(base.gopSize),
+        audioCodec,
+        audioEncoder: audioEncoder.into(),
+        audioBitrate: pick(&settings.audioBitrate, &base.audioBitrate),
+        container,
+        codec,
+        alpha,
+        subtitles: settings
+            .subtitles
+            .iter()
+            .flatten()
+            .map(|track| SubtitleTrack {
+                file: format!("{}/bin/{}", *PROJECT_DIR, track.file),
+                language: track.language.clone(),
+            })
+            .collect(),
+        metadata: settings.metadata.clone(),
+    })
+}
+
+impl EncoderSettings {
+    /// Whether frames go to the encoder as RGB rather than YUV.
+    pub fn isRgb(&self) -> bool {
+        self.codec == "png"
+    }
+
+    /// Output file extension.
+    pub fn extension(&self) -> &str {
+        &self.container
+    }
+
+    pub fn videoArgs(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec!["-c:v".into(), self.encoder.clone()];
+
+        match self.codec.as_str() {
+            "prores" => {
+                // ProRes has no rate control, the profile sets the quality
+                let profile = match self.preset.as_deref().unwrap_or("hq") {
+                    "proxy" => "0",
+                    "lt" => "1",
+                    "standard" => "2",
+                    "4444" => "4",
+                    "4444xq" => "5",
+                    _ => "3",
+                };
+                args.extend(["-profile:v".into(), profile.into()]);
+                if self.alpha {
+                    args.extend(["-alpha_bits".into(), "16".into()]);
+                }
+            }
+            "png" => {}
+            "vp9" => {
+                match (&self.bitrate, self.crf) {
+                    (Some(bitrate), _) => args.extend(["-b:v".into(), bitrate.clone()]),
+                    // constant quality mode needs an unbounded bitrate
+                    (None, Some(crf)) => args.extend(["-crf".into(), crf.to_string(), "-b:v".into(), "0".into()]),
+                    _ => {}
+                }
+                if let Some(speed) = &self.preset {
+                    args.extend(["-deadline".into(), "good".into(), "-cpu-used".into(), speed.clone()]);
+                }
+                if self.alpha {
+                    // libvpx drops alpha when alt-ref frames are on
+                    args.extend(["-auto-alt-ref".into(), "0".into()]);
+                }
+            }
+            _ => {
+                match (&self.bitrate, self.crf) {
+                    (Some(bitrate), _) => args.extend(["-b:v".into(), bitrate.clone()]),
+                    (None, Some(crf)) => args.extend(["-crf".into(), crf.to_string()]),
+                    _ => {}
+                }
+                if let Some(preset) = &self.preset {
+                    args.extend(["-preset".into(), preset.clone()]);
+                }
+            }
+        }
+
+        args.extend(["-pix_fmt".into(), self.pixelFormat.clone()]);
+        if let Some(gop) = self.gopSize {
+            args.extend(["-g".into(), gop.to_string()]);
+        }
+        args.extend(self.muxArgs());
+        args
+    }
+
+    /// Container flags, also needed when joining segments without re-encoding.
+    pub fn muxArgs(&self) -> Vec<String> {
+        if self.container == "mp4" || self.container == "mov" {
+            // let players start before the whole file has downloaded
+            return vec!["-movflags".into(), "+faststart".into()];
+        }
+        Vec::new()
+    }
+
+    /// Inputs and output arguments muxing in the subtitle tracks, numbered from `firstInput`.
+    /// `offset` shifts them back, for renders that start part way into the scene.
+    pub fn subtitleArgs(&self, firstInput: usize, offset: f64) -> (Vec<String>, Vec<String>) {
+        let mut inputs: Vec<String> = Vec::new();
+        let mut args: Vec<String> = Vec::new();
+        for (i, track) in self.subtitles.iter().enumerate() {
+            if offset > 0.0 {
+                inputs.extend(["-itsoffset".into(), (-offset).to_string()]);
+            }
+            inputs.extend(["-i".into(), track.file.clone()]);
+
+            // mp4 and mov only hold mov_text, webm only WebVTT; mkv keeps each file's format
+            let codec = match (self.container.as_str(), track.file.rsplit('.').next()) {
+                ("mp4" | "mov", _) => "mov_text",
+                ("webm", _) | (_, Some("vtt")) => "webvtt",
+                (_, Some("ass")) => "ass",
+                _ => "srt",
+            };
+            args.extend([
+                "-map".into(),
+                format!("{}:s", firstInput + i),
+                format!("-c:s:{}", i),
+                codec.into(),
+            ]);
+            if let Some(language) = &track.language {
+                args.extend([format!("-metadata:s:s:{}", i), format!("language={}", language)]);
+            }
+        }
+        (inputs, args)
+    }
+
+    pub fn audioArgs(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec!["-c:a".into(), self.audioEncoder.clone()];
+        if let Some(bitrate) = &self.audioBitrate {
+            args.extend(["-b:a".into(), bitrate.clone()]);
+        }
+        args
+    }
+
+    /// Checks the settings against what the installed ffmpeg can actually do, so a render
+    /// fails before any frames are generated rather than at the end.
+    pub fn checkCapabilities(&self) -> Result<(), String> {
+        // container/codec combinations
+        let (videoOk, audioOk): (&[&str], &[&str]) = match self.container.as_str() {
+            "mp4" => (&["h264", "h265", "av1", "vp9"], &["aac", "mp3", "opus", "flac"]),
+            "mov" => (&["h264", "h265", "prores", "png"], &["aac", "pcm"]),
+            "webm" => (&["vp9", "av1"], &["opus"]),
+            "mkv" => (&["h264", "h265", "vp9", "av1", "prores", "png"], &["aac", "opus", "mp3", "flac", "pcm"]),
+            _ => return Err(format!("unsupported container: {}", self.container)),
+        };
+        if !videoOk.contains(&self.codec.as_str()) {
+            return Err(format!("{} cannot hold {} video", self.container, self.codec));
+        }
+        if !audioOk.contains(&self.audioCodec.as_str()) {
+            return Err(format!("{} cannot hold {} audio", self.container, self.audioCodec));
+        }
+        if self.alpha && self.container == "mp4" {
+            return Err("mp4 cannot hold video with alpha, use mov, webm or mkv".into());
+        }
+        for track in self.subtitles.iter() {
+            if !std::path::Path::new(&track.file).exists() {
+                return Err(format!("subtitle file {} does not exist", track.file));
+            }
+            if self.container == "webm" && !track.file.ends_with(".vtt") {
+                return Err(format!("webm only holds WebVTT subtitles, not {}", track.file));
+            }
+        }
+
+        let encoders = ffmpegList(&["-encoders"])?;
+        for encoder in [&self.encoder, &self.audioEncoder] {
+            if !encoders.contains(encoder.as_str()) {
+                return Err(format!("installed ffmpeg has no {} encoder", encoder));
+            }
+        }
+
+        let muxer = if self.container == "mkv" { "matroska" } else { self.container.as_str() };
+        if !ffmpegList(&["-muxers"])?.contains(muxer) {
+            return Err(format!("installed ffmpeg cannot write {} files", self.container));
+        }
+
+        let pixelFormats = encoderPixelFormats(&self.encoder)?;
+        if !pixelFormats.is_empty() && !pixelFormats.contains(&self.pixelFormat) {
+            return Err(format!(
+                "{} does not support pixel format {} (supported: {})",
+                self.encoder,
+                self.pixelFormat,
+                pixelFormats.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+// names from `ffmpeg -encoders`/`-muxers`, whose listings are "<flags> <name> <description>"
+fn ffmpegList(args: &[&str]) -> Result<HashSet<String>, String> {
+    let output = std::process::Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run ffmpeg: {}", e))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    // skip the legend, which ends with a "--" line
+    Ok(text
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("--"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .flat_map(|names| names.split(',').map(|n| n.to_string()).collect::<Vec<_>>())
+        .collect())
+}
+
+fn encoderPixelFormats(encoder: &str) -> Result<Vec<String>, String> {
+    let output = std::process::Command::new("ffmpeg")
+        .args(["-hide_banner", "-h", &format!("encoder={}", encoder)])
+        .output()
+        .map_err(|e| format!("failed to run ffmpeg: {}", e))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    Ok(text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Supported pixel formats:"))
+        .map(|formats| formats.split_whitespace().map(|f| f.to_string()).collect())
+        .unwrap_or_default())
+}
@@ -0,0 +1,116 @@
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// Region of the source, in source pixels.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+const FIT_MODES: [&str; 4] = ["stretch", "contain", "cover", "none"];
+
+/// Crops then fits an image into `width`x`height`.
+///
+/// - "stretch": scale to fill, ignoring aspect ratio
+/// - "contain": scale to fit inside, bars filled with `letterbox`
+/// - "cover": scale to fill, overflow cropped evenly
+/// - "none": keep source scale, centred, cropped or padded as needed
+pub fn fitImage(
+    img: &RgbaImage,
+    width: u32,
+    height: u32,
+    fit: &str,
+    letterbox: [u8; 4],
+    crop: Option<&Rect>,
+) -> Result<RgbaImage, String> {
+    if !FIT_MODES.contains(&fit) {
+        return Err(format!("unknown fit mode {fit}"));
+    }
+    let cropped;
+    let src = match crop {
+        Some(rect) => {
+            let (w, h) = img.dimensions();
+            let x = rect.x.min(w);
+            let y = rect.y.min(h);
+            cropped = imageops::crop_imm(img, x, y, rect.width.min(w - x), rect.height.min(h - y)).to_image();
+            &cropped
+        }
+        None => img,
+    };
+    let (srcW, srcH) = src.dimensions();
+    if srcW == 0 || srcH == 0 || width == 0 || height == 0 {
+        return Ok(RgbaImage::from_pixel(width, height, Rgba(letterbox)));
+    }
+
+    Ok(match fit {
+        "contain" | "cover" => {
+            let scaleW = width as f64 / srcW as f64;
+            let scaleH = height as f64 / srcH as f64;
+            let scale = if fit == "contain" { scaleW.min(scaleH) } else { scaleW.max(scaleH) };
+            let w = ((srcW as f64 * scale).round() as u32).max(1);
+            let h = ((srcH as f64 * scale).round() as u32).max(1);
+            let resized = imageops::resize(src, w, h, FilterType::Lanczos3);
+            centre(&resized, width, height, letterbox)
+        }
+        "none" => centre(src, width, height, letterbox),
+        _ if (srcW, srcH) == (width, height) => src.clone(),
+        _ => imageops::resize(src, width, height, FilterType::Lanczos3),
+    })
+}
+
+/// Equivalent ffmpeg filter chain for `fitImage`, producing `width`x`height` rgba frames.
+pub fn fitFilter(
+    width: u32,
+    height: u32,
+    fit: &str,
+    letterbox: [u8; 4],
+    crop: Option<&Rect>,
+) -> Result<String, String> {
+    if !FIT_MODES.contains(&fit) {
+        return Err(format!("unknown fit mode {fit}"));
+    }
+    let mut filters = Vec::new();
+    if let Some(rect) = crop {
+        filters.push(format!("crop={}:{}:{}:{}", rect.width, rect.height, rect.x, rect.y));
+    }
+
+    let colour = format!(
+        "0x{:02x}{:02x}{:02x}{:02x}",
+        letterbox[0], letterbox[1], letterbox[2], letterbox[3]
+    );
+    let pad = format!("pad={width}:{height}:(ow-iw)/2:(oh-ih)/2:color={colour}");
+    match fit {
+        "contain" => {
+            filters.push(format!("scale={width}:{height}:force_original_aspect_ratio=decrease"));
+            // pad needs an alpha channel to honour a transparent letterbox
+            filters.push("format=rgba".into());
+            filters.push(pad);
+        }
+        "cover" => {
+            filters.push(format!("scale={width}:{height}:force_original_aspect_ratio=increase"));
+            filters.push(format!("crop={width}:{height}"));
+        }
+        "none" => {
+            filters.push(format!("crop='min(iw,{width})':'min(ih,{height})'"));
+            filters.push("format=rgba".into());
+            filters.push(pad);
+        }
+        _ => filters.push(format!("scale={width}x{height}")),
+    }
+    Ok(filters.join(","))
+}
+
+// place `src` centred on a `width`x`height` canvas, cropping any overflow
+fn centre(src: &RgbaImage, width: u32, height: u32, letterbox: [u8; 4]) -> RgbaImage {
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba(letterbox));
+    let x = (width as i64 - src.width() as i64) / 2;
+    let y = (height as i64 - src.height() as i64) / 2;
+    imageops::replace(&mut canvas, src, x, y);
+    canvas
+}
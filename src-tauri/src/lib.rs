@@ -2,22 +2,36 @@
 #![allow(non_snake_case)]
 
 use std::clone::Clone;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::io::{Cursor, Read, Write};
+use std::io::Read;
 use std::path::PathBuf;
 use std::process::Stdio;
 use std::sync::Arc;
 use std::time::Instant;
 
-use base64::Engine;
 use dotenvy::dotenv;
 use hound::{SampleFormat, WavSpec, WavWriter};
-use image::{ImageFormat, RgbaImage};
+use image::RgbaImage;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
+mod audio;
+mod blend;
 mod cache;
+mod captions;
+mod colour;
+mod draft;
+mod encode;
+mod fit;
+mod metadata;
+mod output;
+mod pipeline;
+mod precompute;
+mod sequence;
+mod sinks;
+mod subtitles;
+mod thumbnail;
 // use cache::{readCache, writeCache, hashAudioFile};
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -25,10 +39,21 @@ struct Scene {
     id: String,
     fps: u32,
     canvasSize: CanvasSize,
+    colourSpace: Option<String>, // working colour space: "srgb" | "displayP3" | "bt2020"; the root scene's applies to the whole tree
+    background: Option<[u8; 4]>, // RGBA canvas fill, transparent by default
     props: HashMap<String, Prop>,
-    audio: Option<String>,
+    audio: Option<String>,                    // relative to public/
+    audioTracks: Option<Vec<audio::AudioTrack>>, // mixed with `audio` and the video props' audio
+    music: Option<audio::MusicBed>,             // ducked under everything else
+    captions: Option<captions::Captions>,        // burned in over every prop; root scene only
+    metadata: Option<metadata::Metadata>,        // container tags and chapters; root scene only
+    watermark: Option<bool>,                     // stamp DRAFT on every frame, set for drafts; root scene only
     precompute: Vec<Scene>,
     frames: Vec<Script>,
+
+    // how a parent draws this scene when used as a precompute
+    compositeType: Option<String>,
+    opacity: Option<f32>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -41,25 +66,52 @@ struct CanvasSize {
 struct Prop {
     id: String,
     sprites: Vec<String>,
-    propType: String,      // "image" | "video"
-    compositeType: String, // "copy" | "paste"
+    propType: String,      // "image" | "video" | "imageSequence" | "colour"
+    compositeType: String, // "paste" | "overlay" | "add" | "screen" | "multiply"
+    opacity: Option<f32>,
 
     width: Option<u32>,
     height: Option<u32>,
     colour: Option<[u8; 3]>,
+    playback: Option<Playback>,
+    fps: Option<f64>, // imageSequence only; plays by time when set
+
+    fit: Option<String>,        // "stretch" | "contain" | "cover" | "none"
+    letterbox: Option<[u8; 4]>, // RGBA, for "contain" and "none"
+    crop: Option<fit::Rect>,    // applied before fitting
+    scale: Option<f32>,         // image and imageSequence only: resamples sprites by this after fitting, e.g. for drafts
+    sourceColourSpace: Option<String>, // video only: "bt709" | "bt601" | "bt2020" | "srgb" | "displayP3"; defaults to the stream's tags, then bt709
+
+    muted: Option<bool>,     // video only; otherwise its audio is mixed in while it's on stage
+    audioGain: Option<f64>,  // dB
 
     disabled: Option<bool>,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+struct Playback {
+    startTime: Option<f64>, // scene time (s) at which the clip starts
+    inPoint: Option<f64>,   // clip time (s)
+    outPoint: Option<f64>,  // clip time (s)
+    rate: Option<f64>,
+    reverse: Option<bool>,
+    loopMode: Option<String>, // "clamp" | "loop" | "pingPong"
+}
+
 #[derive(Debug, Clone)]
 struct LoadedProp {
     id: String,
-    sprites: Vec<RgbaImage>,
+    sprites: Vec<cache::Sprite>,
     propType: String,      // "image" | "video"
     compositeType: String, // "copy" | "paste"
 
     width: u32,
     height: u32,
+    fps: Option<f64>, // sprites per second, for time-based playback
+    playback: Option<Playback>,
+    colour: Option<[u8; 3]>, // colour props are filled at draw time rather than stored as sprites
+    scene: Option<Arc<precompute::LazyScene>>, // precomputes are rendered on demand rather than stored as sprites
+    opacity: f32,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -77,6 +129,7 @@ struct StageDirection {
     y: u32,
     width: Option<u32>,
     height: Option<u32>,
+    colour: Option<[u8; 3]>, // overrides a colour prop's fill for this frame
 }
 
 #[derive(Serialize)]
@@ -87,6 +140,7 @@ struct VideoData {
     audioSampleRate: u32,
     datetime: String, // ISO string
     fps: f64,
+    colourPrimaries: String, // the stream's tag, e.g. "bt709"; empty if untagged
 }
 
 static TEMP_DIR: Lazy<PathBuf> = Lazy::new(|| {
@@ -115,9 +169,14 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             renderFrame,
             renderVideo,
+            renderAnimation,
+            renderThumbnail,
+            benchmarkRender,
             extractAudio,
             analyseAudio,
+            exportSubtitles,
             getVideoData,
+            clearAssetCache,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -128,12 +187,43 @@ fn generateFrame(
     script: Script,
     props: Arc<HashMap<String, LoadedProp>>,
     canvasSize: Arc<CanvasSize>,
+    fps: u32,
+    background: [u8; 4],
+    captionTrack: Option<&captions::CaptionTrack>,
 ) -> Result<Vec<u8>, String> {
     // spawn blocking compute
     let startTotal = Instant::now();
 
     // 1. prepare blank canvas
-    let mut canvas = RgbaImage::new(canvasSize.width, canvasSize.height);
+    let mut canvas = RgbaImage::from_pixel(canvasSize.width, canvasSize.height, image::Rgba(background));
+
+    // render the precompute frames this frame needs up front; they don't depend on
+    // each other at this level, so can be rendered in parallel
+    let sceneTime = frame as f64 / fps.max(1) as f64;
+    let mut pending: Vec<(&precompute::LazyScene, usize)> = Vec::new();
+    for stageDirection in script.props.iter() {
+        if let Some(lazy) = props.get(&stageDirection.prop).and_then(|p| p.scene.as_deref()) {
+            let index = lazy.frameIndex(sceneTime, stageDirection.sprite);
+            if !pending.iter().any(|(l, i)| std::ptr::eq(*l, lazy) && *i == index) {
+                pending.push((lazy, index));
+            }
+        }
+    }
+    if pending.len() > 1 {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = pending
+                .iter()
+                .map(|(lazy, index)| {
+                    let props = props.clone();
+                    scope.spawn(move || lazy.frame(*index, props))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().map_err(|_| "precompute render panicked".to_string())?)
+                .collect::<Result<Vec<_>, String>>()
+        })?;
+    }
 
     // 2. composite images
     for stageDirection in script.props.iter() {
@@ -155,203 +245,379 @@ fn generateFrame(
         // scale image if needed to prop.width/prop.height
         // let imgResized = img.resize_exact(stageDirection.width, stageDirection.height, image::imageops::FilterType::Nearest);
 
+        if loadedProp.propType == "colour" {
+            // fill directly, the colour may change every frame
+            let colour = stageDirection
+                .colour
+                .or(loadedProp.colour)
+                .ok_or(format!("Colour prop {} has no colour value", &loadedProp.id))?;
+            let colour = [colour[0], colour[1], colour[2], 255];
+            blend::compositeRect(
+                &mut canvas,
+                px as f32,
+                py as f32,
+                width as f32,
+                height as f32,
+                colour,
+                &loadedProp.compositeType,
+                loadedProp.opacity,
+            );
+            continue;
+        }
+
         // overlay on canvas
         let spriteIndex = match loadedProp.propType.as_str() {
-            "video" => stageDirection
-                .sprite
-                .unwrap_or(0)
-                .min(loadedProp.sprites.len() - 1),
+            "video" | "imageSequence" => match stageDirection.sprite {
+                // an explicit sprite overrides time-based playback
+                Some(sprite) => sprite.min(loadedProp.sprites.len().saturating_sub(1)),
+                None if loadedProp.fps.is_some() => {
+                    timedSpriteIndex(loadedProp, frame as f64 / fps.max(1) as f64)
+                }
+                None => 0,
+            },
             _ => stageDirection.sprite.unwrap_or(0),
         };
-        if loadedProp.compositeType == "paste" {
-            fastCopyImage(&mut canvas, &loadedProp.sprites[spriteIndex], px, py);
+        let precomputed;
+        let sprite = match &loadedProp.scene {
+            Some(lazy) => {
+                let index = lazy.frameIndex(sceneTime, stageDirection.sprite);
+                precomputed = lazy.frame(index, props.clone())?;
+                &*precomputed
+            }
+            None => loadedProp
+                .sprites
+                .get(spriteIndex)
+                .ok_or(format!("prop {} has no sprite {}", loadedProp.id, spriteIndex))?,
+        };
+        if loadedProp.compositeType == "paste" && loadedProp.opacity >= 1.0 {
+            fastCopyImage(&mut canvas, sprite, px, py);
         }
-        else if loadedProp.compositeType == "overlay" {
+        else if loadedProp.compositeType == "overlay" && loadedProp.opacity >= 1.0 {
             image::imageops::overlay(
                 &mut canvas,
-                &loadedProp.sprites[spriteIndex],
+                sprite,
                 px as i64,
                 py as i64,
             );
         }
+        else {
+            // translucent paste falls back to blending over what's underneath
+            blend::composite(
+                &mut canvas,
+                sprite,
+                px as i64,
+                py as i64,
+                &loadedProp.compositeType,
+                loadedProp.opacity,
+            );
+        }
     }
 
-    // 4. return data
+    // 4. captions go over everything
+    if let Some(captionTrack) = captionTrack {
+        captionTrack.draw(&mut canvas, sceneTime);
+    }
+
+    // 5. return data
     println!("Frame {}: {:?}", frame, startTotal.elapsed());
     Ok(canvas.into_raw())
 }
 
 #[tauri::command]
-async fn renderFrame(payload: serde_json::Value) -> Result<String, String> {
+async fn renderFrame(payload: serde_json::Value, draft: Option<draft::DraftSettings>) -> Result<String, String> {
     println!("renderFrame() called");
 
     let scene: Scene =
         serde_json::from_value(payload).map_err(|e| format!("failed to deserialize: {}", e))?;
 
-    // render
-    let frameProp = loadFrame(scene, Some(1))?;
+    // a draft preview is smaller, so quicker to composite and send back
+    let scene = match &draft {
+        Some(draft) => draft::draftScene(scene, draft)?.0,
+        None => scene,
+    };
 
-    // convert the loaded image to base64
-    let mut buf = Cursor::new(Vec::new());
-    let canvas = &frameProp.sprites[0];
-    canvas
-        .write_to(&mut buf, ImageFormat::Png)
-        .map_err(|e| format!("failed to encode PNG: {}", e))?;
-    let b64 = base64::engine::general_purpose::STANDARD.encode(buf.get_ref());
+    // render
+    let dataUrl = pipeline::render(&scene, Some(1), &mut sinks::PngDataUrlSink::default())?;
 
     println!("Frame rendered");
-    Ok(format!("data:image/png;base64,{}", b64))
+    Ok(dataUrl)
 }
 
-fn loadFrame(scene: Scene, stub: Option<u64>) -> Result<LoadedProp, String> {
-    println!("rendering frame of {}", scene.id.clone());
-    // 1. load props
-    let mut props = loadProps(&scene.props, stub)?;
-    let canvasSize = Arc::new(scene.canvasSize.clone());
+/// Loads the props of a whole scene tree into one shared pool, with every precompute
+/// registered alongside them and set up to render lazily as it is reached.
+fn loadSceneProps(scene: &Scene, stub: Option<u64>) -> Result<HashMap<String, LoadedProp>, String> {
+    // 1. resolve the precompute tree, and the order it must be drawn in
+    let precomputes = precompute::flattenPrecomputes(scene)?;
+    let graph = precompute::dependencies(&precomputes);
+    precompute::checkCycles(&graph)?;
+
+    // 2. pool props across the tree, so shared assets are only loaded once
+    let mut pool: HashMap<String, Prop> = scene.props.clone();
+    let mut owners: HashMap<String, String> = pool.keys().map(|id| (id.clone(), scene.id.clone())).collect();
+    for precompute in precomputes.iter() {
+        for (id, prop) in precompute.props.iter() {
+            if let Some(existing) = pool.get(id) {
+                let same = serde_json::to_value(existing).ok() == serde_json::to_value(prop).ok();
+                if !same {
+                    return Err(format!(
+                        "prop {} is defined differently in {} and {}",
+                        id, owners[id], precompute.id
+                    ));
+                }
+                continue;
+            }
+            pool.insert(id.clone(), prop.clone());
+            owners.insert(id.clone(), precompute.id.clone());
+        }
+    }
+    for precompute in precomputes.iter() {
+        if pool.contains_key(&precompute.id) {
+            return Err(format!("precompute {} has the same id as a prop", precompute.id));
+        }
+    }
+
+    // 3. load, in the root's colour space, whatever any scene in the tree draws
+    let colourSpace = scene.colourSpace.clone().unwrap_or(colour::DEFAULT_COLOUR_SPACE.into());
+    let frames: Vec<&Script> = scene
+        .frames
+        .iter()
+        .chain(precomputes.iter().flat_map(|p| p.frames.iter()))
+        .collect();
+    let mut props = loadProps(&pool, &frames, &colourSpace, stub)?;
 
-    // 2. precompute complex assets
-    for precompute in scene.precompute.iter() {
-        println!("precomputing {}", precompute.id.clone());
-        let loaded = loadFrame(precompute.clone(), stub)?;
+    // 4. prepare complex assets
+    for precompute in precomputes {
+        println!(
+            "preparing precompute {} (depends on {:?})",
+            precompute.id, graph[&precompute.id]
+        );
         props.insert(
             precompute.id.clone(),
             LoadedProp {
-                id: loaded.id.clone(),
-                sprites: loaded.sprites.clone(),
+                id: precompute.id.clone(),
+                sprites: Vec::new(),
                 propType: "image".into(),
-                compositeType: "paste".into(),
-                width: loaded.width,
-                height: loaded.height,
+                // keeps its alpha, so transparent precomputes can be layered with their own blend mode
+                compositeType: precompute.compositeType.clone().unwrap_or("paste".into()),
+                width: precompute.canvasSize.width,
+                height: precompute.canvasSize.height,
+                fps: Some(precompute.fps as f64),
+                playback: None,
+                colour: None,
+                opacity: precompute.opacity.unwrap_or(1.0),
+                scene: Some(Arc::new(precompute::LazyScene::new(precompute))),
             },
         );
-        println!("precomputed {}!", precompute.id.clone());
     }
-    for (id, prop) in props.iter() {
-        println!(
-            "{} => type: {}, sprites: {} frames, {}x{}",
-            id,
-            prop.propType,
-            prop.sprites.len(),
-            prop.width,
-            prop.height
-        );
-    }
-    let props = Arc::new(props);
-
-    // 3. generate frames
-    let mut loadedFrames = Vec::new();
-    for (i, frameScript) in scene.frames.iter().enumerate() {
-        let bytes = generateFrame(i, frameScript.clone(), props.clone(), canvasSize.clone(),)?;
-        let image =
-            image::RgbaImage::from_raw(scene.canvasSize.width, scene.canvasSize.height, bytes)
-                .ok_or(format!("invalid canvas size at frame {}", i))?;
-        loadedFrames.push(image);
-    }
-
-    Ok(LoadedProp {
-        id: scene.id.clone(),
-        sprites: loadedFrames,
-        propType: "image".into(),
-        compositeType: "paste".into(),
-        width: scene.canvasSize.width,
-        height: scene.canvasSize.height,
-    })
+    Ok(props)
 }
 
 #[tauri::command]
-async fn renderVideo(payload: serde_json::Value) -> Result<String, String> {
+async fn renderVideo(
+    payload: serde_json::Value,
+    settings: Option<encode::RenderSettings>,
+) -> Result<String, String> {
     println!("renderVideo() called");
 
     // 1. deserialise payload as Scene
     let scene: Scene =
         serde_json::from_value(payload).map_err(|e| format!("failed to deserialize: {}", e))?;
 
-    // 2. load props
-    let mut props = loadProps(&scene.props, None)?;
-    let canvasSize = Arc::new(scene.canvasSize.clone());
+    // 2. drafts shrink the scene up front; frame ranges still count full-rate frames,
+    // so are mapped onto the frames the draft keeps
+    let mut settings = settings.unwrap_or_default();
+    let scene = match &settings.draft {
+        Some(draft) => {
+            let (scene, step) = draft::draftScene(scene, draft)?;
+            settings.start = settings.start.map(|start| start.div_ceil(step));
+            settings.end = settings.end.map(|end| end.div_ceil(step));
+            scene
+        }
+        None => scene,
+    };
 
-    // 3. precompute complex assets
-    for precompute in scene.precompute.iter() {
-        println!("precomputing {}", precompute.id.clone());
-        let loaded = loadFrame(precompute.clone(), None)?;
-        props.insert(
-            precompute.id.clone(),
-            LoadedProp {
-                id: loaded.id.clone(),
-                sprites: loaded.sprites.clone(),
-                propType: "image".into(),
-                compositeType: "paste".into(),
-                width: loaded.width,
-                height: loaded.height,
+    // 3. image sequences skip ffmpeg, writing frames straight into a folder; by default the
+    // same one every time, so a re-run only redraws frames that changed
+    if let Some(sequence) = &settings.sequence {
+        let sequence = encode::resolveSequence(sequence)?;
+        let dir = output::resolveOutputPath(
+            settings.output.as_deref().unwrap_or("{id}"),
+            settings.overwrite.as_deref().unwrap_or("overwrite"),
+            &output::OutputVars {
+                id: &scene.id,
+                preset: sequence.format.as_deref().unwrap_or("png"),
+                width: scene.canvasSize.width,
+                height: scene.canvasSize.height,
+                extension: "",
             },
-        );
+            &PathBuf::from(format!("{}/bin", *PROJECT_DIR)),
+        )?;
+        let outputDir = pipeline::render(&scene, None, &mut sinks::ImageSequenceSink::new(dir, sequence))?;
+        println!("Image sequence rendered");
+        return Ok(outputDir);
     }
-    let props = Arc::new(props);
 
-    // 4. spin up ffmpeg
-    let outputFile = format!("{}/bin/{}.mp4", *PROJECT_DIR, scene.id);
-    let mut ffmpeg = std::process::Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-f",
-            "rawvideo",
-            "-pix_fmt",
-            "rgba",
-            "-video_size",
-            &format!("{}x{}", canvasSize.width, canvasSize.height),
-            "-framerate",
-            &format!("{}", scene.fps),
-            "-i",
-            "-",
-            "-i",
-            &format!(
-                "{}/public/{}",
-                *PROJECT_DIR,
-                scene.audio.ok_or("no audio track provided")?
-            ),
-            "-map",
-            "0:v",
-            "-map",
-            "1:a",
-            "-c:v",
-            "libx264",
-            "-pix_fmt",
-            "yuv420p",
-            // "-shortest",
-            &outputFile.to_string(),
-        ])
-        .stdin(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("ffmpeg failed: {}", e))?;
-
-    // 5. generate frames
-    let stdin = ffmpeg.stdin.as_mut().ok_or("failed to open ffmpeg stdin")?;
-    for (i, frame) in scene.frames.iter().enumerate() {
-        let frameBytes = generateFrame(
-            i,
-            frame.clone(),
-            // cloning Arc does not clone underlying data
-            props.clone(),
-            canvasSize.clone(),
-        )?;
+    // 4. check the frame range, if any
+    let range = match (settings.start, settings.end) {
+        (None, None) => None,
+        (start, end) => {
+            let (start, end) = (start.unwrap_or(0), end.unwrap_or(scene.frames.len()));
+            if start >= end || end > scene.frames.len() {
+                return Err(format!("invalid frame range {}..{} for {} frames", start, end, scene.frames.len()));
+            }
+            Some((start, end))
+        }
+    };
+    let segmentFrames = settings.segmentFrames;
+
+    // 5. resolve encoder settings, and make sure ffmpeg can honour them before rendering anything
+    let template = match (&settings.output, segmentFrames, range) {
+        (Some(template), _, _) => template.clone(),
+        (None, None, Some((start, end))) => format!("{{id}}_{}-{}", start, end),
+        (None, _, _) => "{id}".into(),
+    };
+    let overwrite = settings.overwrite.clone().unwrap_or("version".into());
+    let target = settings.target.clone();
+    let settings = encode::resolve(&settings, scene.fps)?;
+    settings.checkCapabilities()?;
+
+    // 6. pick the output path up front, so a "fail" policy fails before rendering
+    let outputFile = output::resolveOutputPath(
+        &template,
+        &overwrite,
+        &output::OutputVars {
+            id: &scene.id,
+            preset: target.as_deref().unwrap_or(&settings.codec),
+            width: scene.canvasSize.width,
+            height: scene.canvasSize.height,
+            extension: settings.extension(),
+        },
+        &PathBuf::from(format!("{}/bin", *PROJECT_DIR)),
+    )?;
+    if let Some(parent) = outputFile.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+    }
+    let outputFile = outputFile.to_string_lossy().into_owned();
+
+    // 7. render, piping frames into ffmpeg, either in one go or as segments joined at the end;
+    // either way ffmpeg writes to a temporary file, so a failed render leaves earlier ones intact
+    let outputFile = match segmentFrames {
+        Some(segmentFrames) => {
+            let dir = PathBuf::from(format!("{}/bin/{}.segments", *PROJECT_DIR, scene.id));
+            let mut sink = sinks::SegmentedSink::new(dir, outputFile, settings, segmentFrames).withOverwrite(&overwrite);
+            if let Some((start, end)) = range {
+                sink = sink.withRange(start, end);
+            }
+            pipeline::render(&scene, None, &mut sink)?
+        }
+        None => {
+            let mut sink = sinks::FfmpegSink::new(outputFile, settings).withOverwrite(&overwrite);
+            if let Some((start, end)) = range {
+                sink = sink.withRange(start, end);
+            }
+            pipeline::render(&scene, None, &mut sink)?
+        }
+    };
+
+    println!("Video rendered");
+    Ok(outputFile)
+}
+
+#[tauri::command]
+async fn renderAnimation(payload: serde_json::Value, settings: encode::AnimationSettings) -> Result<String, String> {
+    println!("renderAnimation() called");
 
-        // encode video
-        stdin
-            .write_all(&frameBytes)
-            .map_err(|e| format!("failed to write to ffmpeg stdin: {}", e))?;
+    let scene: Scene =
+        serde_json::from_value(payload).map_err(|e| format!("failed to deserialize: {}", e))?;
 
-        println!("generated frame {}/{}", i, scene.frames.len());
+    let settings = encode::resolveAnimation(&settings)?;
+    let format = settings.format.clone().unwrap_or("gif".into());
+    let outputFile = output::resolveOutputPath(
+        settings.output.as_deref().unwrap_or("{id}"),
+        settings.overwrite.as_deref().unwrap_or("version"),
+        &output::OutputVars {
+            id: &scene.id,
+            preset: &format,
+            width: scene.canvasSize.width,
+            height: scene.canvasSize.height,
+            extension: &format,
+        },
+        &PathBuf::from(format!("{}/bin", *PROJECT_DIR)),
+    )?;
+    if let Some(parent) = outputFile.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
     }
+    let outputFile = outputFile.to_string_lossy().into_owned();
+    let outputFile = pipeline::render(&scene, None, &mut sinks::AnimationSink::new(outputFile, settings))?;
 
-    let status = ffmpeg
-        .wait()
-        .map_err(|e| format!("ffmpeg wait error: {}", e))?;
-    if !status.success() {
-        return Err(format!("ffmpeg exited with {}", status));
+    println!("Animation rendered");
+    Ok(outputFile)
+}
+
+#[tauri::command]
+async fn renderThumbnail(payload: serde_json::Value, settings: Option<thumbnail::ThumbnailSettings>) -> Result<String, String> {
+    println!("renderThumbnail() called");
+
+    let scene: Scene =
+        serde_json::from_value(payload).map_err(|e| format!("failed to deserialize: {}", e))?;
+
+    // 1. pick the frames, and the size of the still they make
+    let settings = settings.unwrap_or_default();
+    let extension = match settings.format.as_deref().unwrap_or("png") {
+        "png" => "png",
+        "jpeg" => "jpg",
+        format => return Err(format!("unsupported thumbnail format: {}", format)),
+    };
+    let indices = thumbnail::pickFrames(&scene, &settings)?;
+    let canvas = (scene.canvasSize.width, scene.canvasSize.height);
+    let still = thumbnail::stillSize(canvas, indices.len(), settings.sheet.as_ref());
+
+    // 2. pick every output path up front, so a "fail" policy fails before rendering
+    let sizes = settings
+        .sizes
+        .clone()
+        .unwrap_or(vec![thumbnail::ThumbnailSize { width: still.0, height: Some(still.1) }]);
+    let preset = if settings.sheet.is_some() { "sheet" } else { "thumbnail" };
+    let template = settings.output.clone().unwrap_or(format!("{{id}}_{}_{{resolution}}", preset));
+    let overwrite = settings.overwrite.clone().unwrap_or("version".into());
+    let mut outputs = Vec::new();
+    for size in sizes {
+        let (width, height) = size.dimensions(still);
+        let outputFile = output::resolveOutputPath(
+            &template,
+            &overwrite,
+            &output::OutputVars { id: &scene.id, preset, width, height, extension },
+            &PathBuf::from(format!("{}/bin", *PROJECT_DIR)),
+        )?;
+        if outputs.iter().any(|(_, path)| *path == outputFile) {
+            return Err(format!("more than one size would be written to {}", outputFile.display()));
+        }
+        if let Some(parent) = outputFile.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+        }
+        outputs.push((size, outputFile));
     }
 
-    println!("Video rendered");
-    Ok(outputFile.to_string())
+    // 3. render just those frames
+    println!("thumbnail frames: {:?}", indices);
+    let outputFiles = pipeline::render(&scene, None, &mut sinks::ThumbnailSink::new(indices, settings, outputs))?;
+
+    println!("Thumbnail rendered");
+    Ok(outputFiles)
+}
+
+#[tauri::command]
+async fn benchmarkRender(payload: serde_json::Value) -> Result<String, String> {
+    println!("benchmarkRender() called");
+
+    let scene: Scene =
+        serde_json::from_value(payload).map_err(|e| format!("failed to deserialize: {}", e))?;
+
+    // composite every frame, but throw the output away
+    let stats = pipeline::render(&scene, None, &mut sinks::NullSink::default())?;
+
+    println!("Benchmark finished: {}", stats);
+    Ok(stats)
 }
 
 #[tauri::command]
@@ -437,7 +703,7 @@ fn getVideoData(path: &str) -> Result<VideoData, String> {
             "-select_streams",
             "v:0",
             "-show_entries",
-            "stream=width,height,avg_frame_rate,r_frame_rate,duration",
+            "stream=width,height,avg_frame_rate,r_frame_rate,duration,color_primaries",
             "-show_entries",
             "format_tags=creation_time",
             "-of",
@@ -492,6 +758,8 @@ fn getVideoData(path: &str) -> Result<VideoData, String> {
         fps_str.parse::<f64>().unwrap_or(0.0)
     };
 
+    let colourPrimaries = stream["color_primaries"].as_str().unwrap_or("").to_string();
+
     Ok(VideoData {
         width,
         height,
@@ -499,9 +767,16 @@ fn getVideoData(path: &str) -> Result<VideoData, String> {
         audioSampleRate,
         datetime,
         fps,
+        colourPrimaries,
     })
 }
 
+#[tauri::command]
+fn clearAssetCache() -> Result<u64, String> {
+    println!("clearAssetCache() called");
+    cache::clearAssetCache()
+}
+
 #[tauri::command]
 async fn analyseAudio(
     numSpeakers: Option<u32>,
@@ -601,65 +876,174 @@ async fn analyseAudio(
     .to_string())
 }
 
-fn loadProps(props: &HashMap<String, Prop>, stub: Option<u64>) -> Result<HashMap<String, LoadedProp>, String> {
+#[tauri::command]
+async fn exportSubtitles(
+    id: String,
+    analysis: String,
+    settings: Option<subtitles::SubtitleSettings>,
+) -> Result<String, String> {
+    println!("exportSubtitles() called");
+
+    // analyseAudio's result, as it returned it
+    let analysis: subtitles::Analysis =
+        serde_json::from_str(&analysis).map_err(|e| format!("failed to deserialize: {}", e))?;
+    let settings = settings.unwrap_or_default();
+    let format = settings.format.clone().unwrap_or("srt".into());
+
+    let cues = subtitles::groupCues(&analysis, &settings);
+    let text = subtitles::formatCues(&cues, &format, settings.showSpeakers.unwrap_or(true))?;
+
+    let outputFile = format!("{}/bin/{}.{}", *PROJECT_DIR, id, format);
+    std::fs::write(&outputFile, text).map_err(|e| format!("failed to write {}: {}", outputFile, e))?;
+
+    println!("Subtitles exported: {} cues", cues.len());
+    Ok(outputFile)
+}
+
+/// Which sprites of a prop a scene's frames actually draw.
+#[derive(Default)]
+struct PropUsage {
+    sprites: HashSet<usize>,
+    timed: bool, // drawn without a sprite, so timed props may need any sprite
+}
+
+fn collectPropUsage(frames: &[&Script]) -> HashMap<String, PropUsage> {
+    let mut usage: HashMap<String, PropUsage> = HashMap::new();
+    for frame in frames.iter() {
+        for stageDirection in frame.props.iter() {
+            let entry = usage.entry(stageDirection.prop.clone()).or_default();
+            match stageDirection.sprite {
+                Some(sprite) => {
+                    entry.sprites.insert(sprite);
+                }
+                None => {
+                    entry.sprites.insert(0);
+                    entry.timed = true;
+                }
+            }
+        }
+    }
+    usage
+}
+
+// load only the sprites in `used`; the rest are left as empty placeholders so indices still line up
+fn loadSprites(
+    paths: &[String],
+    used: Option<&HashSet<usize>>,
+    prop: &Prop,
+    colourSpace: &str,
+) -> Result<Vec<cache::Sprite>, String> {
+    let mut loadedSprites = Vec::with_capacity(paths.len());
+    for (i, spritePath) in paths.iter().enumerate() {
+        if used.map(|used| used.contains(&i)).unwrap_or(true) {
+            loadedSprites.push(loadSprite(spritePath, prop, colourSpace)?);
+        }
+        else {
+            loadedSprites.push(cache::ownedSprite(RgbaImage::new(0, 0)));
+        }
+    }
+    Ok(loadedSprites)
+}
+
+fn loadProps(
+    props: &HashMap<String, Prop>,
+    frames: &[&Script],
+    colourSpace: &str,
+    stub: Option<u64>,
+) -> Result<HashMap<String, LoadedProp>, String> {
+    // only decode what the frames reference
+    let usage = collectPropUsage(frames);
+    let mut skipped: Vec<String> = Vec::new();
+
     let mut loadedProps: HashMap<String, LoadedProp> = HashMap::new();
     for (id, prop) in props.iter() {
         if prop.disabled == Some(true) {
             continue;
         }
-        let mut loadedSprites: Vec<RgbaImage> = Vec::new();
+        let Some(propUsage) = usage.get(id) else {
+            skipped.push(format!("{} (unreferenced)", id));
+            continue;
+        };
+
+        let mut loadedSprites: Vec<cache::Sprite> = Vec::new();
+        let mut fps = None;
         if prop.propType == "image" {
-            // load all images as array (spritesheet)
-            for spritePath in prop.sprites.iter() {
-                let img = image::open(spritePath)
-                    .map_err(|e| {
-                        format!(
-                            "failed to open sprite {} for prop {}: {}",
-                            spritePath, &prop.id, e
-                        )
-                    })?
-                    .to_rgba8();
-                loadedSprites.push(img);
-            }
+            // load all used images as array (spritesheet)
+            loadedSprites = loadSprites(&prop.sprites, Some(&propUsage.sprites), prop, colourSpace)?;
+        }
+        else if prop.propType == "imageSequence" {
+            // expand directory/pattern into individual frames
+            let source = prop
+                .sprites
+                .first()
+                .ok_or(format!("image sequence prop {} has no source", &prop.id))?;
+            let paths = sequence::expandSequence(source)?;
+            fps = prop.fps;
+
+            // played by time, so every frame may be shown
+            let used = if propUsage.timed && fps.is_some() { None } else { Some(&propUsage.sprites) };
+            loadedSprites = loadSprites(&paths, used, prop, colourSpace)?;
         }
         else if prop.propType == "video" {
+            // decode at the clip's own (average) frame rate, so variable-rate footage
+            // comes out evenly spaced and can be indexed by time
+            let videoData = getVideoData(&prop.sprites[0])?;
+            let clipFps = if videoData.fps > 0.0 { videoData.fps } else { 30.0 };
+            let source = prop
+                .sourceColourSpace
+                .as_deref()
+                .unwrap_or(colour::sourceColourSpace(&videoData.colourPrimaries));
+
+            let mut filter = fit::fitFilter(
+                prop.width.unwrap_or(1920),
+                prop.height.unwrap_or(1080),
+                prop.fit.as_deref().unwrap_or("stretch"),
+                prop.letterbox.unwrap_or(fit::TRANSPARENT),
+                prop.crop.as_ref(),
+            )
+            .map_err(|e| format!("prop {}: {}", &prop.id, e))?;
+            if let Some(colourFilter) = colour::ffmpegInputFilter(colourSpace, source)? {
+                filter = format!("{},{}", colourFilter, filter);
+            }
+
             // load all frames into image array
             loadedSprites = loadVideoFrames(
                 &prop.sprites[0],
                 prop.width.unwrap_or(1920),
                 prop.height.unwrap_or(1080),
+                &filter,
+                clipFps,
                 stub,
             )?;
+            fps = Some(clipFps);
         }
-        else if prop.propType == "colour" {
-            if let Some(colour) = &prop.colour {
-                // extract RGB
-                let r = colour[0];
-                let g = colour[1];
-                let b = colour[2];
-
-                // create an image of the given size filled with the colour
-                let width = prop.width.unwrap_or(1920);
-                let height = prop.height.unwrap_or(1080);
-                let mut img = image::RgbaImage::new(width, height);
-
-                for px in img.pixels_mut() {
-                    *px = image::Rgba([r, g, b, 255]);
-                }
-                loadedSprites.push(img);
-            }
-            else {
-                return Err(format!("Colour prop {} has no colour value", &prop.id));
-            }
+
+        let loadedCount = loadedSprites.iter().filter(|s| s.width() > 0).count();
+        if loadedCount < loadedSprites.len() {
+            let unused: Vec<usize> = (0..loadedSprites.len())
+                .filter(|i| loadedSprites[*i].width() == 0)
+                .collect();
+            skipped.push(format!(
+                "{}: {}/{} sprites unused {:?}",
+                id,
+                unused.len(),
+                loadedSprites.len(),
+                unused
+            ));
         }
 
         let mut width = 0;
         let mut height = 0;
-        if let Some(first) = loadedSprites.first() {
+        if let Some(first) = loadedSprites.iter().find(|s| s.width() > 0) {
             // read actual dimensions from loaded sprites
             width = first.width();
             height = first.height();
         }
+        else if prop.propType == "colour" {
+            // no sprite; filled with the prop's (or stage direction's) colour when drawn
+            width = prop.width.unwrap_or(1920);
+            height = prop.height.unwrap_or(1080);
+        }
 
         loadedProps.insert(
             id.clone(),
@@ -670,25 +1054,103 @@ fn loadProps(props: &HashMap<String, Prop>, stub: Option<u64>) -> Result<HashMap
                 compositeType: prop.compositeType.clone(),
                 width,
                 height,
+                fps,
+                playback: prop.playback.clone(),
+                colour: prop.colour,
+                scene: None,
+                opacity: prop.opacity.unwrap_or(1.0),
             },
         );
     }
+
+    if !skipped.is_empty() {
+        println!("skipped loading {} unused props/sprites:", skipped.len());
+        for entry in skipped.iter() {
+            println!("  {}", entry);
+        }
+    }
     Ok(loadedProps)
 }
 
+fn loadSprite(path: &str, prop: &Prop, colourSpace: &str) -> Result<cache::Sprite, String> {
+    // sprites are only resized when the prop asks for a fit, otherwise they keep their native size
+    let target = match (&prop.fit, prop.width, prop.height) {
+        (Some(fit), Some(width), Some(height)) => Some((fit.as_str(), width, height)),
+        _ => None,
+    };
+    let letterbox = prop.letterbox.unwrap_or(fit::TRANSPARENT);
+
+    let (width, height) = target.map(|(_, w, h)| (w, h)).unwrap_or((0, 0));
+    let key = cache::hashAssetFile(
+        std::path::Path::new(path),
+        width,
+        height,
+        "rgba",
+        &format!(
+            "image:{}:fit={:?}:{:?}:{:?}{}",
+            colourSpace,
+            target.map(|t| t.0),
+            letterbox,
+            prop.crop,
+            // only in the key when set, so existing cache entries stay valid
+            prop.scale.map(|s| format!(":scale={}", s)).unwrap_or_default()
+        ),
+    )
+    .map_err(|e| format!("failed to open sprite {} for prop {}: {}", path, &prop.id, e))?;
+    if let Some(mut cached) = cache::readAssetCache(&key, Some(1)) {
+        if let Some(img) = cached.pop() {
+            return Ok(img);
+        }
+    }
+
+    let mut img = colour::openImage(path, colourSpace)
+        .map_err(|e| format!("failed to open sprite {} for prop {}: {}", path, &prop.id, e))?;
+    if let Some((mode, width, height)) = target {
+        img = fit::fitImage(&img, width, height, mode, letterbox, prop.crop.as_ref())
+            .map_err(|e| format!("prop {}: {}", &prop.id, e))?;
+    }
+    else if let Some(crop) = &prop.crop {
+        img = fit::fitImage(&img, crop.width, crop.height, "none", letterbox, Some(crop))?;
+    }
+    if let Some(scale) = prop.scale.filter(|s| *s != 1.0) {
+        let width = ((img.width() as f32 * scale).round() as u32).max(1);
+        let height = ((img.height() as f32 * scale).round() as u32).max(1);
+        img = image::imageops::resize(&img, width, height, image::imageops::FilterType::Triangle);
+    }
+
+    if let Err(e) = cache::writeAssetCache(&key, std::slice::from_ref(&img)) {
+        println!("failed to cache sprite {}: {}", path, e);
+    }
+    Ok(cache::ownedSprite(img))
+}
+
 fn loadVideoFrames(
     path: &str,
     width: u32,
     height: u32,
+    filter: &str,
+    fps: f64,
     stub: Option<u64>,
-) -> Result<Vec<RgbaImage>, String> {
+) -> Result<Vec<cache::Sprite>, String> {
+    let key = cache::hashAssetFile(
+        std::path::Path::new(path),
+        width,
+        height,
+        "rgba",
+        &format!("video:fps={}:{}", fps, filter),
+    )
+        .map_err(|e| format!("failed to open video {}: {}", path, e))?;
+    if let Some(frames) = cache::readAssetCache(&key, stub) {
+        println!("loaded {} frames of {} from cache", frames.len(), path);
+        return Ok(frames);
+    }
 
     let mut cmd = std::process::Command::new("ffmpeg")
         .args([
             "-i", path,
             "-f", "rawvideo",
             "-pix_fmt", "rgba",
-            "-vf", &format!("scale={}x{}", width, height),
+            "-vf", &format!("fps={},{}", fps, filter),
             "-",
         ])
         .stdout(Stdio::piped())
@@ -718,13 +1180,72 @@ fn loadVideoFrames(
         }
     }
 
-    let _ = cmd.kill(); // stop ffmpeg early if we exited via stub
-    let _ = cmd.wait();
+    // stop ffmpeg early if we exited via stub; otherwise it must have decoded the whole clip
+    let stopped = stub.is_some_and(|maxFrames| frames.len() as u64 >= maxFrames);
+    if stopped {
+        let _ = cmd.kill();
+    }
+    let status = cmd.wait().map_err(|e| format!("ffmpeg wait error: {}", e))?;
+    if !stopped && !status.success() {
+        return Err(format!("ffmpeg exited with {} while decoding {}", status, path));
+    }
+    if frames.is_empty() {
+        return Err(format!("decoded no frames from {}", path));
+    }
+
+    // only a complete decode is worth caching
+    if stub.is_none() {
+        if let Err(e) = cache::writeAssetCache(&key, &frames) {
+            println!("failed to cache video {}: {}", path, e);
+        }
+    }
+
+    Ok(frames.into_iter().map(cache::ownedSprite).collect())
+}
+
+/// Picks the clip frame to show at `sceneTime` seconds, honouring the prop's trim,
+/// rate, direction and loop mode.
+fn timedSpriteIndex(prop: &LoadedProp, sceneTime: f64) -> usize {
+    let count = prop.sprites.len();
+    if count == 0 {
+        return 0;
+    }
+    let fps = prop.fps.unwrap_or(30.0);
+    let playback = prop.playback.clone().unwrap_or_default();
+
+    // resolve trim against the clip's length
+    let duration = count as f64 / fps;
+    let inPoint = playback.inPoint.unwrap_or(0.0).clamp(0.0, duration);
+    let outPoint = playback.outPoint.unwrap_or(duration).clamp(inPoint, duration);
+    let span = outPoint - inPoint;
+
+    // time elapsed within the clip, before the clip starts hold the first frame
+    let elapsed = ((sceneTime - playback.startTime.unwrap_or(0.0)) * playback.rate.unwrap_or(1.0).abs()).max(0.0);
+    let mut local = if span <= 0.0 {
+        0.0
+    }
+    else {
+        match playback.loopMode.as_deref() {
+            Some("loop") => elapsed.rem_euclid(span),
+            Some("pingPong") => {
+                let t = elapsed.rem_euclid(span * 2.0);
+                if t <= span { t } else { span * 2.0 - t }
+            }
+            _ => elapsed.min(span),
+        }
+    };
+    if playback.reverse == Some(true) {
+        local = span - local;
+    }
 
-    Ok(frames)
+    // convert to a frame, keeping within the trimmed range
+    let first = (inPoint * fps).floor() as usize;
+    let last = ((outPoint * fps).ceil() as usize).saturating_sub(1).max(first);
+    let index = ((inPoint + local) * fps + 1e-6).floor() as usize;
+    index.clamp(first, last).min(count - 1)
 }
 
-fn fastCopyImage(dest: &mut RgbaImage, src: &RgbaImage, x: u32, y: u32) {
+fn fastCopyImage(dest: &mut RgbaImage, src: &cache::Sprite, x: u32, y: u32) {
     let (w, h) = src.dimensions();
     for row in 0..h {
         let destStart = ((y + row) * dest.width() + x) as usize * 4;
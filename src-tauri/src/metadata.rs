@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+use super::subtitles::{self, Analysis, SubtitleSettings};
+use super::{cache, getVideoData, Scene, TEMP_DIR};
+
+// words of a cue used to title a chapter that starts there
+const TITLE_WORDS: usize = 6;
+
+/// Tags and chapters written into the output container.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub episode: Option<String>,      // e.g. "S01E04"
+    pub artist: Option<String>,
+    pub comment: Option<String>,
+    pub creationTime: Option<String>, // ISO 8601; defaults to the source video's
+    pub source: Option<String>,       // video prop whose creation time is carried over, default the first by id
+    pub chapters: Option<Chapters>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Chapters {
+    pub markers: Option<Vec<Chapter>>,       // given outright, over `generate`
+    pub generate: Option<String>,            // "speakers" | "pauses": from the transcript, at speaker changes or long silences,
+                                             // the latter standing in for topic changes
+    pub analysis: Option<Analysis>,          // analyseAudio's result; defaults to the captions'
+    pub grouping: Option<SubtitleSettings>,  // how words are grouped into cues, and speaker names
+    pub minLength: Option<f64>,              // s, shortest generated chapter, default 30
+    pub pause: Option<f64>,                  // "pauses" only: s of silence that starts a chapter, default 2
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Chapter {
+    pub start: f64, // scene time (s); each chapter runs until the next
+    pub title: String,
+}
+
+/// Inputs and output arguments writing the scene's metadata, with `overrides` (the render
+/// settings') taking priority field by field. Chapters go in through an FFMETADATA file,
+/// numbered `firstInput`; `range` clips and shifts them to match a partial render.
+pub fn metadataArgs(
+    scene: &Scene,
+    overrides: Option<&Metadata>,
+    firstInput: usize,
+    range: Option<(usize, usize)>,
+) -> Result<(Vec<String>, Vec<String>), String> {
+    let base = scene.metadata.clone().unwrap_or_default();
+    let overrides = overrides.cloned().unwrap_or_default();
+    let metadata = Metadata {
+        title: overrides.title.or(base.title),
+        episode: overrides.episode.or(base.episode),
+        artist: overrides.artist.or(base.artist),
+        comment: overrides.comment.or(base.comment),
+        creationTime: overrides.creationTime.or(base.creationTime),
+        source: overrides.source.or(base.source),
+        chapters: overrides.chapters.or(base.chapters),
+    };
+
+    let mut args: Vec<String> = Vec::new();
+    let tags = [
+        ("title", metadata.title.clone()),
+        ("episode_id", metadata.episode.clone()),
+        ("artist", metadata.artist.clone()),
+        ("comment", metadata.comment.clone()),
+        ("creation_time", creationTime(scene, &metadata)?),
+    ];
+    for (key, value) in tags {
+        if let Some(value) = value {
+            args.extend(["-metadata".into(), format!("{}={}", key, value)]);
+        }
+    }
+
+    // chapters, in the output's time
+    let fps = scene.fps.max(1) as f64;
+    let (start, end) = range.unwrap_or((0, scene.frames.len()));
+    let (offset, duration) = (start as f64 / fps, (end - start) as f64 / fps);
+    let chapters = match &metadata.chapters {
+        Some(chapters) => makeChapters(scene, chapters)?,
+        None => Vec::new(),
+    };
+    let mut clipped: Vec<(f64, f64, &str)> = Vec::new();
+    for (i, chapter) in chapters.iter().enumerate() {
+        let chapterEnd = chapters.get(i + 1).map(|c| c.start).unwrap_or(f64::INFINITY);
+        let (s, e) = ((chapter.start - offset).max(0.0), (chapterEnd - offset).min(duration));
+        if s < e {
+            clipped.push((s, e, &chapter.title));
+        }
+    }
+    if clipped.is_empty() {
+        return Ok((Vec::new(), args));
+    }
+
+    let mut file = String::from(";FFMETADATA1\n");
+    for (s, e, title) in clipped {
+        file += &format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (s * 1000.0).round() as u64,
+            (e * 1000.0).round() as u64,
+            escape(title)
+        );
+    }
+    // named by content, so renders of the same scene with other ranges or chapters don't clash
+    let key = cache::hashParts(&[file.as_bytes()]);
+    let path = TEMP_DIR.join(format!("{}_{}.chapters.txt", scene.id, &key[..16]));
+    if !path.exists() {
+        std::fs::write(&path, file).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    }
+
+    let inputs = vec!["-f".into(), "ffmetadata".into(), "-i".into(), path.to_string_lossy().into_owned()];
+    args.extend(["-map_chapters".into(), firstInput.to_string()]);
+    Ok((inputs, args))
+}
+
+// the given creation time, or the one recorded in the source video
+fn creationTime(scene: &Scene, metadata: &Metadata) -> Result<Option<String>, String> {
+    if metadata.creationTime.is_some() {
+        return Ok(metadata.creationTime.clone());
+    }
+    let source = match &metadata.source {
+        Some(id) => {
+            let prop = scene.props.get(id).ok_or(format!("metadata source {} is not a prop", id))?;
+            if prop.propType != "video" {
+                return Err(format!("metadata source {} is not a video", id));
+            }
+            prop
+        }
+        None => {
+            let video = scene
+                .props
+                .values()
+                .filter(|p| p.propType == "video" && p.disabled != Some(true))
+                .min_by(|a, b| a.id.cmp(&b.id));
+            let Some(video) = video else {
+                return Ok(None);
+            };
+            video
+        }
+    };
+    let path = source.sprites.first().ok_or(format!("video prop {} has no source", source.id))?;
+    let datetime = getVideoData(path)?.datetime;
+    Ok(Some(datetime).filter(|d| !d.is_empty()))
+}
+
+// chapters in scene time, sorted; generated ones start with one at 0
+fn makeChapters(scene: &Scene, chapters: &Chapters) -> Result<Vec<Chapter>, String> {
+    if let Some(markers) = &chapters.markers {
+        let mut markers = markers.clone();
+        markers.sort_by(|a, b| a.start.total_cmp(&b.start));
+        return Ok(markers);
+    }
+    let Some(generate) = &chapters.generate else {
+        return Ok(Vec::new());
+    };
+
+    let analysis = chapters
+        .analysis
+        .as_ref()
+        .or(scene.captions.as_ref().map(|c| &c.analysis))
+        .ok_or("generated chapters need a transcript, from chapters.analysis or the captions")?;
+    let grouping = chapters
+        .grouping
+        .clone()
+        .or(scene.captions.as_ref().and_then(|c| c.grouping.clone()))
+        .unwrap_or_default();
+    let cues = subtitles::groupCues(analysis, &grouping);
+    let minLength = chapters.minLength.unwrap_or(30.0);
+    let pause = chapters.pause.unwrap_or(2.0);
+
+    let mut out: Vec<Chapter> = Vec::new();
+    for (i, cue) in cues.iter().enumerate() {
+        let title = match generate.as_str() {
+            "speakers" => match &cue.speaker {
+                Some(speaker) => speaker.clone(),
+                None => continue,
+            },
+            "pauses" => {
+                let words: Vec<&str> = cue.words.iter().take(TITLE_WORDS).map(|w| w.word.trim()).collect();
+                let more = if cue.words.len() > TITLE_WORDS { "…" } else { "" };
+                format!("{}{}", words.join(" "), more)
+            }
+            _ => return Err(format!("unknown chapter generator: {}", generate)),
+        };
+        let Some(last) = out.last() else {
+            out.push(Chapter { start: 0.0, title });
+            continue;
+        };
+        let changes = match generate.as_str() {
+            "speakers" => title != last.title,
+            _ => i > 0 && cue.start - cues[i - 1].end >= pause,
+        };
+        if changes && cue.start - last.start >= minLength {
+            out.push(Chapter { start: cue.start, title });
+        }
+    }
+    Ok(out)
+}
+
+// FFMETADATA escapes its special characters with a backslash
+fn escape(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values for the `{name}` placeholders in an output path template.
+pub struct OutputVars<'a> {
+    pub id: &'a str,
+    pub preset: &'a str,
+    pub width: u32,
+    pub height: u32,
+    pub extension: &'a str,
+}
+
+/// Expands an output path template into a path that's safe to write, under `defaultDir` if
+/// it's relative. Placeholders are `{id}`, `{date}`, `{preset}`, `{resolution}` and
+/// `{version}`, which counts up to the first unused path as `v001`, `v002`...; the extension
+/// is added if missing, unless it's empty, for directories.
+///
+/// `overwrite` decides what happens when the path already exists: "fail" returns an error,
+/// "version" appends `_v002`, `_v003`... and "overwrite" replaces the file once the render succeeds.
+pub fn resolveOutputPath(
+    template: &str,
+    overwrite: &str,
+    vars: &OutputVars,
+    defaultDir: &Path,
+) -> Result<PathBuf, String> {
+    if !["fail", "version", "overwrite"].contains(&overwrite) {
+        return Err(format!("unknown overwrite policy: {}", overwrite));
+    }
+
+    let expand = |version: Option<u32>| -> Result<PathBuf, String> {
+        let mut path = String::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            path += &rest[..open];
+            let close = rest[open..]
+                .find('}')
+                .ok_or(format!("unclosed placeholder in output path: {}", template))?;
+            let value = match &rest[open + 1..open + close] {
+                "id" => vars.id.to_string(),
+                "date" => today(),
+                "preset" => vars.preset.to_string(),
+                "resolution" => format!("{}x{}", vars.width, vars.height),
+                "version" => versionLabel(version.unwrap_or(1)),
+                name => return Err(format!("unknown placeholder in output path: {{{}}}", name)),
+            };
+            path += &value;
+            rest = &rest[open + close + 1..];
+        }
+        path += rest;
+
+        let mut path = defaultDir.join(path);
+        if !vars.extension.is_empty() && path.extension().is_none_or(|ext| ext != vars.extension) {
+            path.as_mut_os_string().push(format!(".{}", vars.extension));
+        }
+        Ok(path)
+    };
+
+    // a version counter in the template always moves on to a fresh file
+    if template.contains("{version}") {
+        return (1..)
+            .map(|version| expand(Some(version)))
+            .find(|path| path.as_ref().map_or(true, |p| !p.exists()))
+            .unwrap_or(Err("ran out of versions".into()));
+    }
+
+    let path = expand(None)?;
+    if !path.exists() || overwrite == "overwrite" {
+        return Ok(path);
+    }
+    if overwrite == "fail" {
+        return Err(format!("{} already exists", path.display()));
+    }
+    let name = match vars.extension {
+        "" => path.file_name(),
+        _ => path.file_stem(),
+    };
+    let name = name.unwrap_or_default().to_string_lossy().into_owned();
+    Ok((2..)
+        .map(|version| {
+            let versioned = format!("{}_{}", name, versionLabel(version));
+            match vars.extension {
+                "" => path.with_file_name(versioned),
+                extension => path.with_file_name(format!("{}.{}", versioned, extension)),
+            }
+        })
+        .find(|p| !p.exists())
+        .unwrap_or(path))
+}
+
+/// Renames a finished render from `temp` to `path`, which was picked by `resolveOutputPath`.
+/// Unless the policy is "overwrite", a file that appeared at `path` during the render is left
+/// alone, and the render kept at `temp`.
+pub fn moveIntoPlace(temp: &Path, path: &Path, overwrite: &str) -> Result<(), String> {
+    if overwrite != "overwrite" && path.exists() {
+        return Err(format!(
+            "{} appeared during the render; the render was kept as {}",
+            path.display(),
+            temp.display()
+        ));
+    }
+    std::fs::rename(temp, path).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+fn versionLabel(version: u32) -> String {
+    format!("v{:03}", version)
+}
+
+// today's UTC date as YYYY-MM-DD, from days since the epoch (Howard Hinnant's civil_from_days)
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let dayOfEra = z - era * 146097;
+    let yearOfEra = (dayOfEra - dayOfEra / 1460 + dayOfEra / 36524 - dayOfEra / 146096) / 365;
+    let dayOfYear = dayOfEra - (365 * yearOfEra + yearOfEra / 4 - yearOfEra / 100);
+    let mp = (5 * dayOfYear + 2) / 153;
+    let day = dayOfYear - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yearOfEra + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
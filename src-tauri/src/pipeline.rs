@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use image::RgbaImage;
+
+use super::{captions, draft, fit, generateFrame, loadSceneProps, Scene};
+
+/// Where rendered frames go. The pipeline does the loading, precomputing and generating,
+/// so a sink only has to deal with output.
+pub trait RenderSink {
+    /// Called once, before the first frame.
+    fn begin(&mut self, _scene: &Scene) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Whether frame `index` should be generated at all, e.g. false if it's already on disk.
+    fn needsFrame(&self, _index: usize) -> bool {
+        true
+    }
+
+    fn writeFrame(&mut self, index: usize, frame: RgbaImage) -> Result<(), String>;
+
+    /// Called once all frames are written; returns the sink's result, e.g. an output path.
+    fn finish(&mut self) -> Result<String, String>;
+
+    /// Called instead of `finish` if rendering fails, to clean up partial output.
+    fn abort(&mut self) {}
+}
+
+/// Renders every frame of `scene` into `sink`.
+pub fn render(scene: &Scene, stub: Option<u64>, sink: &mut dyn RenderSink) -> Result<String, String> {
+    match renderFrames(scene, stub, sink) {
+        Ok(()) => sink.finish(),
+        Err(e) => {
+            sink.abort();
+            Err(e)
+        }
+    }
+}
+
+fn renderFrames(scene: &Scene, stub: Option<u64>, sink: &mut dyn RenderSink) -> Result<(), String> {
+    println!("rendering {}", scene.id);
+
+    sink.begin(scene)?;
+    if !(0..scene.frames.len()).any(|i| sink.needsFrame(i)) {
+        println!("every frame is already rendered");
+        return Ok(());
+    }
+
+    // 1. load props, and 2. prepare complex assets
+    let props = loadSceneProps(scene, stub)?;
+    for (id, prop) in props.iter() {
+        println!(
+            "{} => type: {}, sprites: {} frames, {}x{}",
+            id,
+            prop.propType,
+            prop.sprites.len(),
+            prop.width,
+            prop.height
+        );
+    }
+    let props = Arc::new(props);
+    let canvasSize = Arc::new(scene.canvasSize.clone());
+    let captionTrack = scene
+        .captions
+        .as_ref()
+        .map(|c| captions::CaptionTrack::load(c, scene.canvasSize.height))
+        .transpose()?;
+
+    // 3. generate frames
+    for (i, frameScript) in scene.frames.iter().enumerate() {
+        if !sink.needsFrame(i) {
+            continue;
+        }
+        let bytes = generateFrame(
+            i,
+            frameScript.clone(),
+            // cloning Arc does not clone underlying data
+            props.clone(),
+            canvasSize.clone(),
+            scene.fps,
+            scene.background.unwrap_or(fit::TRANSPARENT),
+            captionTrack.as_ref(),
+        )?;
+        let mut frame = RgbaImage::from_raw(canvasSize.width, canvasSize.height, bytes)
+            .ok_or(format!("invalid canvas size at frame {}", i))?;
+        if scene.watermark == Some(true) {
+            draft::watermark(&mut frame);
+        }
+
+        // 4. output
+        sink.writeFrame(i, frame)?;
+        println!("generated frame {}/{}", i, scene.frames.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::MemorySink;
+
+    #[test]
+    fn rendersColourPropsIntoMemory() {
+        let scene: Scene = serde_json::from_value(serde_json::json!({
+            "id": "test",
+            "fps": 10,
+            "canvasSize": { "width": 4, "height": 3 },
+            "background": [0, 0, 255, 255],
+            "props": {
+                "box": { "id": "box", "sprites": [], "propType": "colour", "compositeType": "paste",
+                         "colour": [255, 0, 0], "width": 2, "height": 2 },
+            },
+            "precompute": [],
+            "frames": [
+                { "id": "0", "props": [{ "prop": "box", "x": 0, "y": 0 }] },
+                { "id": "1", "props": [{ "prop": "box", "x": 2, "y": 1, "colour": [0, 255, 0] }] },
+            ],
+        }))
+        .unwrap();
+
+        let mut sink = MemorySink::default();
+        render(&scene, None, &mut sink).unwrap();
+        assert_eq!(sink.frames.len(), 2);
+
+        let (red, green, blue) = ([255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]);
+        let first = &sink.frames[0];
+        assert_eq!(first.dimensions(), (4, 3));
+        assert_eq!(first.get_pixel(0, 0).0, red);
+        assert_eq!(first.get_pixel(1, 1).0, red);
+        assert_eq!(first.get_pixel(2, 0).0, blue);
+        assert_eq!(first.get_pixel(0, 2).0, blue);
+
+        // the stage direction's colour overrides the prop's
+        let second = &sink.frames[1];
+        assert_eq!(second.get_pixel(3, 2).0, green);
+        assert_eq!(second.get_pixel(1, 1).0, blue);
+    }
+
+    #[test]
+    fn blendsColourPropsWithOpacityAndMode() {
+        let scene: Scene = serde_json::from_value(serde_json::json!({
+            "id": "test",
+            "fps": 10,
+            "canvasSize": { "width": 2, "height": 1 },
+            "background": [0, 0, 255, 255],
+            "props": {
+                "glow": { "id": "glow", "sprites": [], "propType": "colour", "compositeType": "add",
+                          "opacity": 0.5, "colour": [255, 0, 0], "width": 1, "height": 1 },
+            },
+            "precompute": [],
+            "frames": [{ "id": "0", "props": [{ "prop": "glow", "x": 0, "y": 0 }] }],
+        }))
+        .unwrap();
+
+        let mut sink = MemorySink::default();
+        render(&scene, None, &mut sink).unwrap();
+        let frame = &sink.frames[0];
+        assert_eq!(frame.get_pixel(0, 0).0, [128, 0, 255, 255]);
+        assert_eq!(frame.get_pixel(1, 0).0, [0, 0, 255, 255]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use super::cache::{FrameData, Sprite};
+use super::{fit, generateFrame, CanvasSize, LoadedProp, Scene};
+
+// recent child frames kept per precompute; parents tend to revisit the same few frames
+const FRAME_CACHE_SIZE: usize = 8;
+
+/// A precompute rendered on demand as the parent reaches each frame.
+///
+/// Props come from the shared pool passed to `frame`, rather than being owned here.
+pub struct LazyScene {
+    pub scene: Scene,
+    pub canvasSize: Arc<CanvasSize>,
+    frames: Mutex<VecDeque<(usize, Arc<Sprite>)>>, // least recently used first
+}
+
+impl std::fmt::Debug for LazyScene {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyScene")
+            .field("id", &self.scene.id)
+            .field("frames", &self.scene.frames.len())
+            .finish()
+    }
+}
+
+impl LazyScene {
+    pub fn new(scene: Scene) -> Self {
+        let canvasSize = Arc::new(scene.canvasSize.clone());
+        LazyScene {
+            scene,
+            canvasSize,
+            frames: Mutex::new(VecDeque::with_capacity(FRAME_CACHE_SIZE)),
+        }
+    }
+
+    /// Child frame shown at the parent's `sceneTime`. An explicit `sprite` picks the child
+    /// frame directly, otherwise it is found by time (looping), so differing fps line up.
+    pub fn frameIndex(&self, sceneTime: f64, sprite: Option<usize>) -> usize {
+        let count = self.scene.frames.len().max(1);
+        match sprite {
+            Some(sprite) => sprite.min(count - 1),
+            None => ((sceneTime.max(0.0) * self.scene.fps as f64 + 1e-6).floor() as usize) % count,
+        }
+    }
+
+    pub fn frame(&self, index: usize, props: Arc<HashMap<String, LoadedProp>>) -> Result<Arc<Sprite>, String> {
+        if let Some(frame) = self.cached(index) {
+            return Ok(frame);
+        }
+
+        let script = self
+            .scene
+            .frames
+            .get(index)
+            .ok_or(format!("precompute {} has no frame {}", self.scene.id, index))?;
+        let bytes = generateFrame(
+            index,
+            script.clone(),
+            props,
+            self.canvasSize.clone(),
+            self.scene.fps,
+            self.scene.background.unwrap_or(fit::TRANSPARENT),
+            None,
+        )?;
+        let image = Sprite::from_raw(self.canvasSize.width, self.canvasSize.height, FrameData::Owned(bytes))
+            .ok_or(format!("invalid canvas size at frame {} of {}", index, self.scene.id))?;
+        let image = Arc::new(image);
+
+        let mut frames = self.frames.lock().map_err(|e| e.to_string())?;
+        if frames.len() >= FRAME_CACHE_SIZE {
+            frames.pop_front();
+        }
+        frames.push_back((index, image.clone()));
+        Ok(image)
+    }
+
+    fn cached(&self, index: usize) -> Option<Arc<Sprite>> {
+        let mut frames = self.frames.lock().ok()?;
+        let position = frames.iter().position(|(i, _)| *i == index)?;
+        // move to the back, as most recently used
+        let entry = frames.remove(position)?;
+        let image = entry.1.clone();
+        frames.push_back(entry);
+        Some(image)
+    }
+}
+
+/// Flattens the precompute tree under `root` into one list, so any scene in the tree
+/// can reference any precompute, not just its direct children.
+pub fn flattenPrecomputes(root: &Scene) -> Result<Vec<Scene>, String> {
+    fn visit(scene: &Scene, out: &mut Vec<Scene>, seen: &mut HashSet<String>) -> Result<(), String> {
+        for child in scene.precompute.iter() {
+            if !seen.insert(child.id.clone()) {
+                return Err(format!("precompute {} is defined more than once", child.id));
+            }
+            visit(child, out, seen)?;
+
+            let mut child = child.clone();
+            child.precompute = Vec::new(); // now held in the flat list
+            out.push(child);
+        }
+        Ok(())
+    }
+
+    let mut out = Vec::new();
+    let mut seen = HashSet::from([root.id.clone()]);
+    visit(root, &mut out, &mut seen)?;
+    Ok(out)
+}
+
+/// Ids of the precomputes each precompute draws, i.e. what must be rendered before it.
+pub fn dependencies(precomputes: &[Scene]) -> HashMap<String, Vec<String>> {
+    let ids: HashSet<&str> = precomputes.iter().map(|p| p.id.as_str()).collect();
+    precomputes
+        .iter()
+        .map(|precompute| {
+            let mut deps: Vec<String> = precompute
+                .frames
+                .iter()
+                .flat_map(|frame| frame.props.iter())
+                .filter(|direction| ids.contains(direction.prop.as_str()))
+                .map(|direction| direction.prop.clone())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            deps.sort();
+            (precompute.id.clone(), deps)
+        })
+        .collect()
+}
+
+/// Fails, naming the chain, if any precompute (indirectly) draws itself.
+pub fn checkCycles(graph: &HashMap<String, Vec<String>>) -> Result<(), String> {
+    // 0 = unvisited, 1 = on the current path, 2 = done
+    fn visit(
+        id: &str,
+        graph: &HashMap<String, Vec<String>>,
+        state: &mut HashMap<String, u8>,
+        path: &mut Vec<String>,
+    ) -> Result<(), String> {
+        match state.get(id).copied().unwrap_or(0) {
+            2 => return Ok(()),
+            1 => {
+                let start = path.iter().position(|p| p == id).unwrap_or(0);
+                let mut chain = path[start..].to_vec();
+                chain.push(id.to_string());
+                return Err(format!("precompute cycle: {}", chain.join(" -> ")));
+            }
+            _ => {}
+        }
+
+        state.insert(id.to_string(), 1);
+        path.push(id.to_string());
+        for dep in graph.get(id).into_iter().flatten() {
+            visit(dep, graph, state, path)?;
+        }
+        path.pop();
+        state.insert(id.to_string(), 2);
+        Ok(())
+    }
+
+    let mut ids: Vec<&String> = graph.keys().collect();
+    ids.sort();
+    let mut state = HashMap::new();
+    for id in ids {
+        visit(id, graph, &mut state, &mut Vec::new())?;
+    }
+    Ok(())
+}
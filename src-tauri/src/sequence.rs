@@ -0,0 +1,186 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+const IMAGE_EXTENSIONS: [&str; 9] = ["png", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff", "exr"];
+
+enum Matcher {
+    Directory,
+    Printf(String, Option<usize>, String), // prefix, zero-padded width, suffix
+    Glob(String),
+}
+
+impl Matcher {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Matcher::Directory => isImage(name),
+            Matcher::Printf(prefix, width, suffix) => matchesPrintf(name, prefix, *width, suffix),
+            Matcher::Glob(pattern) => matchesGlob(name.as_bytes(), pattern.as_bytes()),
+        }
+    }
+}
+
+/// Expands an image sequence source into a naturally sorted list of sprite paths.
+///
+/// `source` may be:
+/// - a directory, taking every image inside it
+/// - a printf pattern, e.g. `blink_%04d.png`
+/// - a glob pattern, e.g. `blink_*.png` (`*` and `?` in the file name only)
+pub fn expandSequence(source: &str) -> Result<Vec<String>, String> {
+    let path = Path::new(source);
+
+    let (dir, matcher) = if path.is_dir() {
+        (path, Matcher::Directory)
+    }
+    else {
+        // a bare pattern's parent is empty, meaning the working directory
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let pattern = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(format!("invalid image sequence source: {}", source))?
+            .to_string();
+
+        if let Some((prefix, width, suffix)) = parsePrintf(&pattern) {
+            (dir, Matcher::Printf(prefix, width, suffix))
+        }
+        else if pattern.contains(['*', '?']) {
+            (dir, Matcher::Glob(pattern))
+        }
+        else {
+            return Err(format!("image sequence source is not a directory or pattern: {}", source));
+        }
+    };
+
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("failed to read image sequence directory {}: {}", dir.display(), e))?;
+    let mut sprites: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().to_str().map(|n| n.to_string()))
+        .filter(|name| matcher.matches(name))
+        .collect();
+
+    if sprites.is_empty() {
+        return Err(format!("image sequence {} matched no files", source));
+    }
+
+    sprites.sort_by(|a, b| naturalCmp(a, b));
+    Ok(sprites
+        .into_iter()
+        .map(|name| dir.join(name).to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Orders strings with embedded numbers by value, so `frame_2` sorts before `frame_10`.
+pub fn naturalCmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = takeDigits(&mut a);
+                let y = takeDigits(&mut b);
+                // compare by value, ignoring leading zeros
+                let xs = x.trim_start_matches('0');
+                let ys = y.trim_start_matches('0');
+                let ord = xs.len().cmp(&ys.len()).then_with(|| xs.cmp(ys));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn takeDigits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+        digits.push(c);
+        chars.next();
+    }
+    digits
+}
+
+fn isImage(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// split `blink_%04d.png` into ("blink_", Some(4), ".png")
+fn parsePrintf(pattern: &str) -> Option<(String, Option<usize>, String)> {
+    let start = pattern.find('%')?;
+    let rest = &pattern[start + 1..];
+    let end = rest.find('d')?;
+    let spec = &rest[..end];
+    if !spec.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let width = if spec.starts_with('0') { spec.parse().ok() } else { None };
+    Some((pattern[..start].to_string(), width, rest[end + 1..].to_string()))
+}
+
+fn matchesPrintf(name: &str, prefix: &str, width: Option<usize>, suffix: &str) -> bool {
+    let Some(middle) = name.strip_prefix(prefix).and_then(|n| n.strip_suffix(suffix)) else {
+        return false;
+    };
+    !middle.is_empty()
+        && middle.chars().all(|c| c.is_ascii_digit())
+        && width.map(|w| middle.len() >= w).unwrap_or(true)
+}
+
+fn matchesGlob(name: &[u8], pattern: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            matchesGlob(name, &pattern[1..]) || (!name.is_empty() && matchesGlob(&name[1..], pattern))
+        }
+        (Some(b'?'), Some(_)) => matchesGlob(&name[1..], &pattern[1..]),
+        (Some(p), Some(n)) if p == n => matchesGlob(&name[1..], &pattern[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes empty frames 2, 1 and 10 named after `prefix` into the working directory, expands
+    // `pattern` there, and checks they come back in order
+    fn checkBare(prefix: &str, pattern: &str) {
+        let names: Vec<String> = [1, 2, 10].iter().map(|i| format!("{}_{:04}.png", prefix, i)).collect();
+        for i in [1, 0, 2] {
+            fs::write(&names[i], b"").unwrap();
+        }
+        let sprites = expandSequence(pattern);
+        for name in &names {
+            let _ = fs::remove_file(name);
+        }
+        let expected: Vec<_> = names.iter().map(|name| Path::new(".").join(name)).collect();
+        let sprites: Vec<_> = sprites.unwrap().iter().map(|sprite| Path::new(sprite).to_path_buf()).collect();
+        assert_eq!(sprites, expected);
+    }
+
+    #[test]
+    fn expandsBarePrintfPattern() {
+        checkBare("seqPrintf", "seqPrintf_%04d.png");
+    }
+
+    #[test]
+    fn expandsBareGlob() {
+        checkBare("seqGlob", "seqGlob_*.png");
+    }
+}
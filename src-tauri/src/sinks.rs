@@ -0,0 +1,879 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::time::Instant;
+
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat, Rgba, Rgba32FImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use super::audio::{self, AudioMix};
+use super::encode::{self, AnimationSettings, EncoderSettings, SequenceSettings};
+use super::pipeline::RenderSink;
+use super::thumbnail::{self, ThumbnailSettings, ThumbnailSize};
+use super::{cache, captions, colour, metadata, output, sequence, Scene};
+
+// frames an image sequence writes between saves of its manifest
+const MANIFEST_INTERVAL: usize = 50;
+
+/// Pipes raw frames into ffmpeg, muxing in the scene's audio mix, if it has any. Frames go in as RGBA,
+/// so the canvas's transparency is kept when the encoder settings ask for alpha.
+///
+/// ffmpeg writes to a `.partial` file, renamed once it exits cleanly, so a crash never
+/// leaves a half-written file under the real name.
+pub struct FfmpegSink {
+    outputFile: String,
+    settings: EncoderSettings,
+    range: Option<(usize, usize)>,
+    withAudio: bool,
+    overwrite: String,
+    ffmpeg: Option<Child>,
+}
+
+impl FfmpegSink {
+    pub fn new(outputFile: String, settings: EncoderSettings) -> Self {
+        FfmpegSink { outputFile, settings, range: None, withAudio: true, overwrite: "overwrite".into(), ffmpeg: None }
+    }
+
+    /// The policy the output path was picked with, checked again once the render is done.
+    pub fn withOverwrite(mut self, overwrite: &str) -> Self {
+        self.overwrite = overwrite.into();
+        self
+    }
+
+    /// Encodes only frames `start..end`, with the audio to match.
+    pub fn withRange(mut self, start: usize, end: usize) -> Self {
+        self.range = Some((start, end));
+        self
+    }
+
+    /// Leaves the audio, subtitles and metadata out, e.g. for segments that get theirs when joined.
+    pub fn withoutAudio(mut self) -> Self {
+        self.withAudio = false;
+        self
+    }
+}
+
+impl RenderSink for FfmpegSink {
+    fn begin(&mut self, scene: &Scene) -> Result<(), String> {
+        let colourSpace = scene.colourSpace.clone().unwrap_or(colour::DEFAULT_COLOUR_SPACE.into());
+        let mut command = std::process::Command::new("ffmpeg");
+        command.args([
+            "-y",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgba",
+            "-video_size",
+            &format!("{}x{}", scene.canvasSize.width, scene.canvasSize.height),
+            "-framerate",
+            &format!("{}", scene.fps),
+            "-i",
+            "-",
+        ]);
+
+        // every input goes before the output options that map them
+        let mut outputArgs: Vec<String> = vec!["-map".into(), "0:v".into()];
+        if self.withAudio {
+            let mut nextInput = 1;
+            if let Some(mix) = audio::mix(scene, nextInput, self.range)? {
+                command.args(&mix.inputs);
+                outputArgs.extend(mix.outputArgs());
+                outputArgs.extend(self.settings.audioArgs());
+                nextInput += mix.inputCount();
+            }
+            let (inputs, args) = metadata::metadataArgs(scene, self.settings.metadata.as_ref(), nextInput, self.range)?;
+            nextInput += inputs.iter().filter(|arg| *arg == "-i").count();
+            command.args(inputs);
+            outputArgs.extend(args);
+            let offset = self.range.map(|(start, _)| start as f64 / scene.fps as f64).unwrap_or(0.0);
+            let (inputs, args) = self.settings.subtitleArgs(nextInput, offset);
+            command.args(inputs);
+            outputArgs.extend(args);
+        }
+        let ffmpeg = command
+            .args(outputArgs)
+            .args(self.settings.videoArgs())
+            .args(colour::ffmpegOutputArgs(&colourSpace, self.settings.isRgb())?)
+            .arg(partialPath(&self.outputFile))
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("ffmpeg failed: {}", e))?;
+        self.ffmpeg = Some(ffmpeg);
+        Ok(())
+    }
+
+    fn needsFrame(&self, index: usize) -> bool {
+        self.range.is_none_or(|(start, end)| (start..end).contains(&index))
+    }
+
+    fn writeFrame(&mut self, _index: usize, frame: RgbaImage) -> Result<(), String> {
+        let stdin = self
+            .ffmpeg
+            .as_mut()
+            .and_then(|f| f.stdin.as_mut())
+            .ok_or("failed to open ffmpeg stdin")?;
+
+        // encode video
+        stdin
+            .write_all(frame.as_raw())
+            .map_err(|e| format!("failed to write to ffmpeg stdin: {}", e))
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let mut ffmpeg = self.ffmpeg.take().ok_or("ffmpeg was not started")?;
+        drop(ffmpeg.stdin.take()); // signal end of input
+
+        let status = ffmpeg
+            .wait()
+            .map_err(|e| format!("ffmpeg wait error: {}", e))?;
+        if !status.success() {
+            return Err(format!("ffmpeg exited with {}", status));
+        }
+        output::moveIntoPlace(&partialPath(&self.outputFile), Path::new(&self.outputFile), &self.overwrite)?;
+        Ok(self.outputFile.clone())
+    }
+
+    fn abort(&mut self) {
+        if let Some(mut ffmpeg) = self.ffmpeg.take() {
+            let _ = ffmpeg.kill();
+            let _ = ffmpeg.wait();
+            let _ = std::fs::remove_file(partialPath(&self.outputFile));
+        }
+    }
+}
+
+// "out.mp4" -> "out.partial.mp4", keeping the extension ffmpeg picks the muxer from
+fn partialPath(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    match path.extension() {
+        Some(ext) => path.with_extension(format!("partial.{}", ext.to_string_lossy())),
+        None => path.with_extension("partial"),
+    }
+}
+
+/// Which segments of a render are finished, so a crashed or re-run render can pick up
+/// where it left off. Stored as `manifest.json` next to the segments.
+#[derive(Serialize, Deserialize, Default)]
+struct SegmentManifest {
+    sceneHash: String,
+    segmentFrames: usize,
+    segments: HashMap<usize, String>, // segment index -> hash of the frames it was encoded from
+}
+
+/// Encodes fixed-length segments into `dir`, then joins them with ffmpeg's concat demuxer
+/// without re-encoding. Segments are silent; the audio is muxed in once at the join, so
+/// there are no seams at the cuts.
+///
+/// Each finished segment is checkpointed in a manifest, keyed by a hash of its frames and
+/// everything they depend on. A later render skips segments whose hash still matches, so it
+/// resumes after a crash, and after a fix only the segments that changed are re-encoded.
+/// Segments overlapping the render range, if given, are re-encoded regardless.
+pub struct SegmentedSink {
+    dir: PathBuf,
+    outputFile: String,
+    settings: EncoderSettings,
+    segmentFrames: usize,
+    range: Option<(usize, usize)>,
+    overwrite: String,
+    header: Option<Scene>,
+    totalFrames: usize,
+    manifest: SegmentManifest,
+    segmentHashes: Vec<String>,
+    stale: Vec<bool>,
+    audio: Option<AudioMix>,
+    metadata: (Vec<String>, Vec<String>), // inputs and output arguments, from metadata::metadataArgs
+    current: Option<(usize, FfmpegSink)>,
+}
+
+impl SegmentedSink {
+    pub fn new(dir: PathBuf, outputFile: String, settings: EncoderSettings, segmentFrames: usize) -> Self {
+        SegmentedSink {
+            dir,
+            outputFile,
+            settings,
+            segmentFrames: segmentFrames.max(1),
+            range: None,
+            overwrite: "overwrite".into(),
+            header: None,
+            totalFrames: 0,
+            manifest: SegmentManifest::default(),
+            segmentHashes: Vec::new(),
+            stale: Vec::new(),
+            audio: None,
+            metadata: (Vec::new(), Vec::new()),
+            current: None,
+        }
+    }
+
+    /// Re-encodes the segments overlapping frames `start..end`, even if they look finished.
+    pub fn withRange(mut self, start: usize, end: usize) -> Self {
+        self.range = Some((start, end));
+        self
+    }
+
+    /// As `FfmpegSink::withOverwrite`, for the joined file.
+    pub fn withOverwrite(mut self, overwrite: &str) -> Self {
+        self.overwrite = overwrite.into();
+        self
+    }
+
+    fn segmentPath(&self, segment: usize) -> PathBuf {
+        self.dir.join(format!("{:05}.{}", segment, self.settings.extension()))
+    }
+
+    fn segmentCount(&self) -> usize {
+        self.totalFrames.div_ceil(self.segmentFrames)
+    }
+
+    fn manifestPath(&self) -> PathBuf {
+        self.dir.join("manifest.json")
+    }
+
+    // written to a temporary file then renamed, so a crash can't leave it half-written
+    fn saveManifest(&self) -> Result<(), String> {
+        let path = self.manifestPath();
+        let temp = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(&self.manifest).map_err(|e| e.to_string())?;
+        std::fs::write(&temp, json).map_err(|e| format!("failed to write {}: {}", temp.display(), e))?;
+        std::fs::rename(&temp, &path).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    fn finishSegment(&mut self) -> Result<(), String> {
+        if let Some((segment, mut done)) = self.current.take() {
+            done.finish()?;
+            self.manifest.segments.insert(segment, self.segmentHashes[segment].clone());
+            self.saveManifest()?;
+        }
+        Ok(())
+    }
+
+    fn join(&self) -> Result<(), String> {
+        let list = self.dir.join("segments.txt");
+        let entries: Vec<String> = (0..self.segmentCount())
+            // the concat list quotes like a shell
+            .map(|segment| format!("file '{}'", self.segmentPath(segment).display().to_string().replace('\'', "'\\''")))
+            .collect();
+        std::fs::write(&list, entries.join("\n"))
+            .map_err(|e| format!("failed to write {}: {}", list.display(), e))?;
+
+        let mut command = std::process::Command::new("ffmpeg");
+        command.args(["-y", "-f", "concat", "-safe", "0", "-i"]).arg(&list);
+        let mut outputArgs: Vec<String> = vec!["-map".into(), "0:v".into()];
+        let mut nextInput = 1;
+        if let Some(mix) = &self.audio {
+            command.args(&mix.inputs);
+            outputArgs.extend(mix.outputArgs());
+            outputArgs.extend(self.settings.audioArgs());
+            nextInput += mix.inputCount();
+        }
+        let (inputs, args) = &self.metadata;
+        command.args(inputs);
+        outputArgs.extend(args.iter().cloned());
+        nextInput += inputs.iter().filter(|arg| *arg == "-i").count();
+        let (inputs, args) = self.settings.subtitleArgs(nextInput, 0.0);
+        command.args(inputs);
+        outputArgs.extend(args);
+
+        let partial = partialPath(&self.outputFile);
+        let status = command
+            .args(outputArgs)
+            .args(["-c:v", "copy"])
+            .args(self.settings.muxArgs())
+            .arg(&partial)
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format!("ffmpeg failed: {}", e))?;
+        if !status.success() {
+            let _ = std::fs::remove_file(&partial);
+            return Err(format!("ffmpeg exited with {} while joining segments", status));
+        }
+        output::moveIntoPlace(&partial, Path::new(&self.outputFile), &self.overwrite)
+    }
+}
+
+// everything a frame's pixels depend on besides its own directions, for hashing; going
+// through serde_json::Value sorts the prop maps, so the hash is stable
+fn frameContext(scene: &Scene, settings: String) -> Result<Vec<u8>, String> {
+    let mut assets = BTreeMap::new();
+    assetKeys(scene, &mut assets);
+    serde_json::to_vec(&serde_json::json!({
+        "settings": settings,
+        "assets": assets,
+        "fps": scene.fps,
+        "canvasSize": scene.canvasSize,
+        "colourSpace": scene.colourSpace,
+        "background": scene.background,
+        "props": scene.props,
+        "precompute": scene.precompute,
+        "captions": scene.captions,
+        "watermark": scene.watermark,
+    }))
+    .map_err(|e| e.to_string())
+}
+
+// the content key of every file the scene's props draw from, through the precompute tree,
+// so replacing a source in place invalidates what was drawn from it
+fn assetKeys(scene: &Scene, keys: &mut BTreeMap<String, String>) {
+    for prop in scene.props.values().filter(|p| p.disabled != Some(true)) {
+        let files = match prop.propType.as_str() {
+            "image" => prop.sprites.clone(),
+            "video" => prop.sprites.iter().take(1).cloned().collect(),
+            "imageSequence" => match prop.sprites.first() {
+                Some(source) => sequence::expandSequence(source).unwrap_or_default(),
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        for file in files {
+            // a file that can't be read fails the render when it's loaded, not here
+            let key = cache::hashAssetFile(Path::new(&file), 0, 0, "source", "").unwrap_or_default();
+            keys.insert(file, key);
+        }
+    }
+    for precompute in &scene.precompute {
+        assetKeys(precompute, keys);
+    }
+}
+
+impl RenderSink for SegmentedSink {
+    fn begin(&mut self, scene: &Scene) -> Result<(), String> {
+        self.audio = audio::mix(scene, 1, None)?;
+        let nextInput = 1 + self.audio.as_ref().map(|mix| mix.inputCount()).unwrap_or(0);
+        self.metadata = metadata::metadataArgs(scene, self.settings.metadata.as_ref(), nextInput, None)?;
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("failed to create {}: {}", self.dir.display(), e))?;
+        self.totalFrames = scene.frames.len();
+
+        // subtitles and metadata are only muxed in at the join
+        let segmentSettings = EncoderSettings { subtitles: Vec::new(), metadata: None, ..self.settings.clone() };
+        let context = frameContext(scene, format!("{:?} {}", segmentSettings, self.segmentFrames))?;
+        let frames = |start: usize, end: usize| serde_json::to_vec(&scene.frames[start..end]).unwrap_or_default();
+        self.segmentHashes = (0..self.segmentCount())
+            .map(|segment| {
+                let start = segment * self.segmentFrames;
+                let end = (start + self.segmentFrames).min(self.totalFrames);
+                cache::hashParts(&[&context, &frames(start, end)])
+            })
+            .collect();
+        let sceneHash = cache::hashParts(&[&context, &frames(0, self.totalFrames)]);
+
+        // pick up the previous render's manifest, if it's readable
+        self.manifest = std::fs::read_to_string(self.manifestPath())
+            .ok()
+            .and_then(|json| serde_json::from_str::<SegmentManifest>(&json).ok())
+            .unwrap_or_default();
+        if self.manifest.sceneHash == sceneHash {
+            println!("resuming render of scene {}", &sceneHash[..12]);
+        }
+        self.manifest.sceneHash = sceneHash;
+        self.manifest.segmentFrames = self.segmentFrames;
+        let count = self.segmentCount();
+        self.manifest.segments.retain(|&segment, _| segment < count);
+
+        // segments to encode: forced by the range, or not finished with matching content
+        let forced = self.range.map(|(start, end)| {
+            (start / self.segmentFrames, end.div_ceil(self.segmentFrames))
+        });
+        self.stale = (0..count)
+            .map(|segment| {
+                forced.is_some_and(|(first, last)| (first..last).contains(&segment))
+                    || self.manifest.segments.get(&segment) != Some(&self.segmentHashes[segment])
+                    || !self.segmentPath(segment).exists()
+            })
+            .collect();
+        let finished = self.stale.iter().filter(|s| !**s).count();
+        if finished > 0 {
+            println!("{}/{} segments already finished", finished, count);
+        }
+
+        // segments only need the scene's format, not its content
+        self.header = Some(Scene {
+            id: scene.id.clone(),
+            fps: scene.fps,
+            canvasSize: scene.canvasSize.clone(),
+            colourSpace: scene.colourSpace.clone(),
+            background: scene.background,
+            props: HashMap::new(),
+            audio: None,
+            audioTracks: None,
+            music: None,
+            captions: None,
+            metadata: None,
+            watermark: None,
+            precompute: Vec::new(),
+            frames: Vec::new(),
+            compositeType: None,
+            opacity: None,
+        });
+        Ok(())
+    }
+
+    fn needsFrame(&self, index: usize) -> bool {
+        self.stale.get(index / self.segmentFrames).copied().unwrap_or(false)
+    }
+
+    fn writeFrame(&mut self, index: usize, frame: RgbaImage) -> Result<(), String> {
+        let segment = index / self.segmentFrames;
+        if self.current.as_ref().map(|(s, _)| *s) != Some(segment) {
+            self.finishSegment()?;
+            let start = segment * self.segmentFrames;
+            let end = (start + self.segmentFrames).min(self.totalFrames);
+            let path = self.segmentPath(segment).to_string_lossy().into_owned();
+            let mut sink = FfmpegSink::new(path, self.settings.clone())
+                .withRange(start, end)
+                .withoutAudio();
+            sink.begin(self.header.as_ref().ok_or("segmented render was not started")?)?;
+            println!("encoding segment {}/{}", segment, self.segmentCount());
+            self.current = Some((segment, sink));
+        }
+        let (_, sink) = self.current.as_mut().ok_or("no segment open")?;
+        sink.writeFrame(index, frame)
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        self.finishSegment()?;
+        self.join()?;
+        Ok(self.outputFile.clone())
+    }
+
+    fn abort(&mut self) {
+        // the segment's partial file goes, finished segments stay for the next run
+        if let Some((_, mut sink)) = self.current.take() {
+            sink.abort();
+        }
+    }
+}
+
+/// Keeps the first frame, returned as a PNG data URL for previews.
+#[derive(Default)]
+pub struct PngDataUrlSink {
+    frame: Option<RgbaImage>,
+}
+
+impl RenderSink for PngDataUrlSink {
+    fn needsFrame(&self, index: usize) -> bool {
+        index == 0
+    }
+
+    fn writeFrame(&mut self, _index: usize, frame: RgbaImage) -> Result<(), String> {
+        if self.frame.is_none() {
+            self.frame = Some(frame);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let canvas = self.frame.take().ok_or("scene has no frames")?;
+
+        // convert the loaded image to base64
+        let mut buf = Cursor::new(Vec::new());
+        canvas
+            .write_to(&mut buf, ImageFormat::Png)
+            .map_err(|e| format!("failed to encode PNG: {}", e))?;
+        let b64 = base64::engine::general_purpose::STANDARD.encode(buf.get_ref());
+        Ok(format!("data:image/png;base64,{}", b64))
+    }
+}
+
+/// Writes each frame as a numbered image in `dir`, with the audio mix alongside as a WAV.
+///
+/// Frames are written to a temporary name then renamed, so any frame already on disk is
+/// complete. A manifest records what each was drawn from, so an interrupted render picks up
+/// where it left off, and after a change only the frames it touches are redrawn.
+pub struct ImageSequenceSink {
+    dir: PathBuf,
+    settings: SequenceSettings,
+    prefix: String,
+    colourSpace: String,
+    audio: Option<AudioMix>,
+    manifest: SequenceManifest,
+    frameHashes: Vec<String>,
+    unsaved: usize,
+}
+
+/// Which frames of an image sequence are on disk and what they were rendered from, so a re-run
+/// only redraws frames that are missing or have changed. Stored as `{prefix}.manifest.json`.
+#[derive(Serialize, Deserialize, Default)]
+struct SequenceManifest {
+    frames: HashMap<usize, String>, // frame index -> hash of everything it was drawn from
+}
+
+impl ImageSequenceSink {
+    /// `settings` must already be resolved by `encode::resolveSequence`.
+    pub fn new(dir: PathBuf, settings: SequenceSettings) -> Self {
+        ImageSequenceSink {
+            dir,
+            settings,
+            prefix: String::new(),
+            colourSpace: colour::DEFAULT_COLOUR_SPACE.into(),
+            audio: None,
+            manifest: SequenceManifest::default(),
+            frameHashes: Vec::new(),
+            unsaved: 0,
+        }
+    }
+
+    fn manifestPath(&self) -> PathBuf {
+        self.dir.join(format!("{}.manifest.json", self.prefix))
+    }
+
+    // as SegmentedSink::saveManifest
+    fn saveManifest(&mut self) -> Result<(), String> {
+        let path = self.manifestPath();
+        let temp = path.with_extension("json.tmp");
+        let json = serde_json::to_string(&self.manifest).map_err(|e| e.to_string())?;
+        std::fs::write(&temp, json).map_err(|e| format!("failed to write {}: {}", temp.display(), e))?;
+        std::fs::rename(&temp, &path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    fn framePath(&self, index: usize) -> PathBuf {
+        let format = self.settings.format.as_deref().unwrap_or("png");
+        let number = index + self.settings.startNumber.unwrap_or(0);
+        let padding = self.settings.padding.unwrap_or(5);
+        self.dir.join(format!("{}_{:0padding$}.{}", self.prefix, number, format))
+    }
+
+    fn encode(&self, frame: RgbaImage, path: &Path) -> Result<(), String> {
+        let format = self.settings.format.as_deref().unwrap_or("png");
+        let sixteenBit = self.settings.bitDepth == Some(16);
+        let result = match format {
+            "exr" => {
+                // EXR is scene-linear; alpha is already linear
+                let table = colour::linearTable(&self.colourSpace)?;
+                let mut linear = Rgba32FImage::new(frame.width(), frame.height());
+                for (out, pixel) in linear.pixels_mut().zip(frame.pixels()) {
+                    let [r, g, b, a] = pixel.0;
+                    *out = Rgba([table[r as usize], table[g as usize], table[b as usize], a as f32 / 255.0]);
+                }
+                linear.save_with_format(path, ImageFormat::OpenExr)
+            }
+            "tiff" if sixteenBit => DynamicImage::ImageRgba8(frame).into_rgba16().save_with_format(path, ImageFormat::Tiff),
+            "tiff" => frame.save_with_format(path, ImageFormat::Tiff),
+            // the canvas is 8-bit, so 16-bit output is for pipelines that expect it, not extra precision
+            _ if sixteenBit => DynamicImage::ImageRgba8(frame).into_rgba16().save_with_format(path, ImageFormat::Png),
+            _ => frame.save_with_format(path, ImageFormat::Png),
+        };
+        result.map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+impl RenderSink for ImageSequenceSink {
+    fn begin(&mut self, scene: &Scene) -> Result<(), String> {
+        self.prefix = self.settings.prefix.clone().unwrap_or(scene.id.clone());
+        self.colourSpace = scene.colourSpace.clone().unwrap_or(colour::DEFAULT_COLOUR_SPACE.into());
+        self.audio = audio::mix(scene, 0, None)?;
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("failed to create {}: {}", self.dir.display(), e))?;
+
+        let context = frameContext(scene, format!("{:?}", self.settings))?;
+        self.frameHashes = scene
+            .frames
+            .iter()
+            .map(|frame| cache::hashParts(&[&context, &serde_json::to_vec(frame).unwrap_or_default()]))
+            .collect();
+        self.manifest = std::fs::read_to_string(self.manifestPath())
+            .ok()
+            .and_then(|json| serde_json::from_str::<SequenceManifest>(&json).ok())
+            .unwrap_or_default();
+        self.manifest.frames.retain(|&index, _| index < scene.frames.len());
+
+        let existing = (0..scene.frames.len()).filter(|&i| !self.needsFrame(i)).count();
+        if existing > 0 {
+            println!("resuming: {}/{} frames already on disk", existing, scene.frames.len());
+        }
+        Ok(())
+    }
+
+    fn needsFrame(&self, index: usize) -> bool {
+        self.manifest.frames.get(&index) != self.frameHashes.get(index) || !self.framePath(index).exists()
+    }
+
+    fn writeFrame(&mut self, index: usize, frame: RgbaImage) -> Result<(), String> {
+        let path = self.framePath(index);
+        let temp = path.with_extension("tmp");
+        if let Err(e) = self.encode(frame, &temp) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+        std::fs::rename(&temp, &path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+
+        // checkpointed every so often rather than every frame; a frame missing from the
+        // manifest after a crash is just drawn again
+        self.manifest.frames.insert(index, self.frameHashes[index].clone());
+        self.unsaved += 1;
+        if self.unsaved >= MANIFEST_INTERVAL {
+            self.saveManifest()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        self.saveManifest()?;
+
+        // export the audio, fitted to the scene's length
+        if let Some(mix) = &self.audio {
+            let wav = self.dir.join(format!("{}.wav", self.prefix));
+            let status = std::process::Command::new("ffmpeg")
+                .arg("-y")
+                .args(&mix.inputs)
+                .args(mix.outputArgs())
+                .args(["-c:a", "pcm_s16le"])
+                .arg(&wav)
+                .stderr(Stdio::null())
+                .status()
+                .map_err(|e| format!("ffmpeg failed: {}", e))?;
+            if !status.success() {
+                return Err(format!("ffmpeg exited with {} while exporting audio", status));
+            }
+        }
+        Ok(self.dir.to_string_lossy().into_owned())
+    }
+
+    fn abort(&mut self) {
+        let _ = self.saveManifest();
+    }
+}
+
+/// Encodes a frame range into an animated GIF or WebP. Clips are short, so frames are held in
+/// memory until the end, letting the encode be retried smaller until it fits `maxBytes`.
+pub struct AnimationSink {
+    outputFile: String,
+    settings: AnimationSettings,
+    fps: u32,
+    frames: Vec<RgbaImage>,
+}
+
+impl AnimationSink {
+    /// `settings` must already be resolved by `encode::resolveAnimation`.
+    pub fn new(outputFile: String, settings: AnimationSettings) -> Self {
+        AnimationSink { outputFile, settings, fps: 0, frames: Vec::new() }
+    }
+
+    // encodes every frame to `path`, returning the file size
+    fn encode(&self, path: &str, colours: u32, quality: u32, width: u32) -> Result<u64, String> {
+        let first = self.frames.first().ok_or("frame range is empty")?;
+        let mut ffmpeg = std::process::Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+                "-video_size",
+                &format!("{}x{}", first.width(), first.height()),
+                "-framerate",
+                &self.fps.to_string(),
+                "-i",
+                "-",
+            ])
+            .args(encode::animationArgs(&self.settings, colours, quality, width))
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("ffmpeg failed: {}", e))?;
+
+        let stdin = ffmpeg.stdin.as_mut().ok_or("failed to open ffmpeg stdin")?;
+        for frame in &self.frames {
+            stdin
+                .write_all(frame.as_raw())
+                .map_err(|e| format!("failed to write to ffmpeg stdin: {}", e))?;
+        }
+        drop(ffmpeg.stdin.take());
+
+        let status = ffmpeg
+            .wait()
+            .map_err(|e| format!("ffmpeg wait error: {}", e))?;
+        if !status.success() {
+            return Err(format!("ffmpeg exited with {}", status));
+        }
+        std::fs::metadata(path)
+            .map(|m| m.len())
+            .map_err(|e| format!("failed to read {}: {}", path, e))
+    }
+}
+
+impl RenderSink for AnimationSink {
+    fn begin(&mut self, scene: &Scene) -> Result<(), String> {
+        let start = self.settings.start.unwrap_or(0);
+        if start >= scene.frames.len() {
+            return Err(format!("frame range starts at {}, but the scene has {} frames", start, scene.frames.len()));
+        }
+        self.fps = scene.fps;
+        Ok(())
+    }
+
+    fn needsFrame(&self, index: usize) -> bool {
+        index >= self.settings.start.unwrap_or(0) && self.settings.end.is_none_or(|end| index < end)
+    }
+
+    fn writeFrame(&mut self, _index: usize, frame: RgbaImage) -> Result<(), String> {
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let webp = self.settings.format.as_deref() == Some("webp");
+        let canvasWidth = self.frames.first().map(|f| f.width()).unwrap_or(0);
+        let mut colours = self.settings.colours.unwrap_or(256);
+        let mut quality = self.settings.quality.unwrap_or(75);
+        let mut width = self.settings.width.unwrap_or(canvasWidth).min(canvasWidth);
+
+        // cut colours (gif) or quality (webp) first, as they cost less than resolution
+        let temp = format!("{}.part", self.outputFile);
+        loop {
+            let size = self.encode(&temp, colours, quality, width).inspect_err(|_| {
+                let _ = std::fs::remove_file(&temp);
+            })?;
+            let Some(maxBytes) = self.settings.maxBytes else { break };
+            if size <= maxBytes {
+                break;
+            }
+            println!("{} bytes is over {}, shrinking", size, maxBytes);
+            if !webp && colours > 32 {
+                colours /= 2;
+            } else if webp && quality > 40 {
+                quality -= 15;
+            } else if width > 160 {
+                width = width * 3 / 4;
+            } else {
+                let _ = std::fs::remove_file(&temp);
+                return Err(format!("could not fit the clip into {} bytes", maxBytes));
+            }
+        }
+        let overwrite = self.settings.overwrite.as_deref().unwrap_or("version");
+        output::moveIntoPlace(Path::new(&temp), Path::new(&self.outputFile), overwrite)?;
+        Ok(self.outputFile.clone())
+    }
+}
+
+/// Renders only the frames a thumbnail needs, then lays them out, titles and saves the still
+/// once per size.
+pub struct ThumbnailSink {
+    indices: Vec<usize>,
+    settings: ThumbnailSettings,
+    outputs: Vec<(ThumbnailSize, PathBuf)>,
+    frames: HashMap<usize, RgbaImage>,
+}
+
+impl ThumbnailSink {
+    /// `indices` come from `thumbnail::pickFrames`, and `outputs` pair each size with its path.
+    pub fn new(indices: Vec<usize>, settings: ThumbnailSettings, outputs: Vec<(ThumbnailSize, PathBuf)>) -> Self {
+        ThumbnailSink { indices, settings, outputs, frames: HashMap::new() }
+    }
+
+    fn encode(&self, still: RgbaImage, path: &Path) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        let result = match self.settings.format.as_deref().unwrap_or("png") {
+            "jpeg" => {
+                let quality = self.settings.quality.unwrap_or(90).clamp(1, 100);
+                DynamicImage::ImageRgba8(still)
+                    .into_rgb8()
+                    .write_with_encoder(JpegEncoder::new_with_quality(std::io::BufWriter::new(file), quality))
+            }
+            _ => still.write_to(&mut std::io::BufWriter::new(file), ImageFormat::Png),
+        };
+        result.map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+impl RenderSink for ThumbnailSink {
+    fn needsFrame(&self, index: usize) -> bool {
+        self.indices.contains(&index)
+    }
+
+    fn writeFrame(&mut self, index: usize, frame: RgbaImage) -> Result<(), String> {
+        self.frames.insert(index, frame);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let background = self.settings.background.unwrap_or([0, 0, 0, 255]);
+        let frames = self
+            .indices
+            .iter()
+            .map(|i| self.frames.remove(i).ok_or(format!("frame {} was not rendered", i)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // flatten onto the background, as JPEG has no alpha and thumbnails rarely want it
+        let still = match &self.settings.sheet {
+            Some(sheet) => thumbnail::contactSheet(&frames, sheet, background),
+            None => {
+                let frame = frames.into_iter().next().ok_or("no frame to use")?;
+                let mut still = RgbaImage::from_pixel(frame.width(), frame.height(), Rgba(background));
+                image::imageops::overlay(&mut still, &frame, 0, 0);
+                still
+            }
+        };
+
+        let mut paths = Vec::new();
+        for (size, path) in &self.outputs {
+            let mut sized = size.resize(&still)?;
+            if let Some(title) = &self.settings.title {
+                captions::drawTitle(&mut sized, title)?;
+            }
+            let temp = path.with_extension("tmp");
+            if let Err(e) = self.encode(sized, &temp) {
+                let _ = std::fs::remove_file(&temp);
+                return Err(e);
+            }
+            let overwrite = self.settings.overwrite.as_deref().unwrap_or("version");
+            output::moveIntoPlace(&temp, path, overwrite)?;
+            paths.push(path.to_string_lossy().into_owned());
+        }
+        Ok(serde_json::json!(paths).to_string())
+    }
+}
+
+/// Collects every frame in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySink {
+    pub frames: Vec<RgbaImage>,
+}
+
+#[cfg(test)]
+impl RenderSink for MemorySink {
+    fn writeFrame(&mut self, _index: usize, frame: RgbaImage) -> Result<(), String> {
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        Ok(format!("{} frames", self.frames.len()))
+    }
+}
+
+/// Discards frames, timing the render; for benchmarking the compositor.
+#[derive(Default)]
+pub struct NullSink {
+    start: Option<Instant>,
+    frames: usize,
+}
+
+impl RenderSink for NullSink {
+    fn begin(&mut self, _scene: &Scene) -> Result<(), String> {
+        self.start = Some(Instant::now());
+        Ok(())
+    }
+
+    fn writeFrame(&mut self, _index: usize, _frame: RgbaImage) -> Result<(), String> {
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let elapsed = self.start.map(|s| s.elapsed().as_secs_f64()).unwrap_or(0.0);
+        Ok(serde_json::json!({
+            "frames": self.frames,
+            "seconds": elapsed,
+            "fps": if elapsed > 0.0 { self.frames as f64 / elapsed } else { 0.0 },
+        })
+        .to_string())
+    }
+}
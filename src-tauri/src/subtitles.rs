@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::audio::Span;
+
+// a palette for ASS speaker styles, as &HBBGGRR
+const SPEAKER_COLOURS: [&str; 6] = ["&H00FFFFFF", "&H0000FFFF", "&H00FFFF00", "&H0000FF00", "&H00FF80FF", "&H000080FF"];
+
+/// A word from `analyseAudio`'s transcript.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Word {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// What `analyseAudio` returns: pyannote's speaker spans and WhisperX's words.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Analysis {
+    pub speakers: HashMap<String, Vec<Span>>,
+    pub script: Vec<Word>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SubtitleSettings {
+    pub format: Option<String>,                  // "srt" | "vtt" | "ass"
+    pub maxChars: Option<usize>,                 // per line, default 42; cues hold up to two lines
+    pub maxDuration: Option<f64>,                // s per cue, default 6
+    pub pause: Option<f64>,                      // s of silence that ends a cue, default 0.6
+    pub speakerNames: Option<HashMap<String, String>>, // e.g. "SPEAKER_00" -> "Pengwyn"
+    pub showSpeakers: Option<bool>,              // label cues with their speaker, default true
+}
+
+/// One subtitle on screen.
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub lines: Vec<String>,
+    pub speaker: Option<String>,
+    pub words: Vec<Word>,
+    pub split: usize, // index of the first word on the second line, or words.len()
+}
+
+/// Groups words into cues, breaking at pauses, speaker changes, and the length limits.
+pub fn groupCues(analysis: &Analysis, settings: &SubtitleSettings) -> Vec<Cue> {
+    let maxChars = settings.maxChars.unwrap_or(42).max(1);
+    let maxDuration = settings.maxDuration.unwrap_or(6.0);
+    let pause = settings.pause.unwrap_or(0.6);
+
+    let mut cues: Vec<Cue> = Vec::new();
+    let mut words: Vec<&Word> = Vec::new();
+    let mut speaker: Option<String> = None;
+    for word in analysis.script.iter().filter(|w| !w.word.trim().is_empty()) {
+        let wordSpeaker = speakerAt(&analysis.speakers, (word.start + word.end) / 2.0);
+        if let (Some(first), Some(last)) = (words.first(), words.last()) {
+            let text: usize = words.iter().map(|w| w.word.trim().len() + 1).sum::<usize>() + word.word.trim().len();
+            let breaks = word.start - last.end >= pause
+                || word.end - first.start > maxDuration
+                || text > maxChars * 2
+                || (wordSpeaker.is_some() && wordSpeaker != speaker);
+            if breaks {
+                cues.push(makeCue(&words, speaker.take(), maxChars));
+                words.clear();
+            }
+        }
+        if words.is_empty() {
+            speaker = wordSpeaker;
+        }
+        words.push(word);
+    }
+    if !words.is_empty() {
+        cues.push(makeCue(&words, speaker, maxChars));
+    }
+
+    // names for the speakers, if given
+    if let Some(names) = &settings.speakerNames {
+        for cue in cues.iter_mut() {
+            if let Some(name) = cue.speaker.as_ref().and_then(|s| names.get(s)) {
+                cue.speaker = Some(name.clone());
+            }
+        }
+    }
+    cues
+}
+
+// the speaker talking at `time`, or the nearest one within a second, as pyannote's spans
+// don't always cover the edges of words
+fn speakerAt(speakers: &HashMap<String, Vec<Span>>, time: f64) -> Option<String> {
+    speakers
+        .iter()
+        .flat_map(|(speaker, spans)| spans.iter().map(move |span| (speaker, span)))
+        .map(|(speaker, span)| (speaker, (span.start - time).max(time - span.end).max(0.0)))
+        .filter(|(_, distance)| *distance < 1.0)
+        .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(b.0)))
+        .map(|(speaker, _)| speaker.clone())
+}
+
+// splits the cue's text into at most two lines, as evenly as the words allow
+fn makeCue(words: &[&Word], speaker: Option<String>, maxChars: usize) -> Cue {
+    let text: Vec<&str> = words.iter().map(|w| w.word.trim()).collect();
+    let full = text.join(" ");
+    let (lines, split) = if full.len() <= maxChars || text.len() < 2 {
+        (vec![full], text.len())
+    } else {
+        let split = (1..text.len())
+            .min_by_key(|&i| text[..i].join(" ").len().abs_diff(text[i..].join(" ").len()))
+            .unwrap_or(text.len());
+        (vec![text[..split].join(" "), text[split..].join(" ")], split)
+    };
+    Cue {
+        start: words.first().map(|w| w.start).unwrap_or(0.0),
+        end: words.last().map(|w| w.end).unwrap_or(0.0),
+        lines,
+        speaker,
+        words: words.iter().map(|w| (*w).clone()).collect(),
+        split,
+    }
+}
+
+/// Writes cues as SRT, WebVTT or ASS.
+pub fn formatCues(cues: &[Cue], format: &str, showSpeakers: bool) -> Result<String, String> {
+    match format {
+        "srt" => Ok(cues
+            .iter()
+            .enumerate()
+            .map(|(i, cue)| {
+                let mut lines = cue.lines.clone();
+                if let (true, Some(speaker)) = (showSpeakers, &cue.speaker) {
+                    lines[0] = format!("{}: {}", speaker, lines[0]);
+                }
+                format!("{}\n{} --> {}\n{}\n", i + 1, timestamp(cue.start, ','), timestamp(cue.end, ','), lines.join("\n"))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")),
+        "vtt" => {
+            let mut out = String::from("WEBVTT\n");
+            for cue in cues {
+                let text = cue.lines.join("\n");
+                let text = match (showSpeakers, &cue.speaker) {
+                    (true, Some(speaker)) => format!("<v {}>{}", speaker, text),
+                    _ => text,
+                };
+                out += &format!("\n{} --> {}\n{}\n", timestamp(cue.start, '.'), timestamp(cue.end, '.'), text);
+            }
+            Ok(out)
+        }
+        "ass" => {
+            // one style per speaker, so they can be told apart by colour
+            let mut speakers: Vec<&str> = cues.iter().filter_map(|c| c.speaker.as_deref()).collect();
+            speakers.sort();
+            speakers.dedup();
+            let mut out = String::from(
+                "[Script Info]\nScriptType: v4.00+\nPlayResX: 1920\nPlayResY: 1080\n\n\
+                 [V4+ Styles]\nFormat: Name, Fontname, Fontsize, PrimaryColour, OutlineColour, BackColour, \
+                 Bold, Italic, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV\n\
+                 Style: Default,Arial,56,&H00FFFFFF,&H00000000,&H80000000,0,0,1,3,0,2,60,60,60\n",
+            );
+            for (i, speaker) in speakers.iter().enumerate() {
+                out += &format!(
+                    "Style: {},Arial,56,{},&H00000000,&H80000000,0,0,1,3,0,2,60,60,60\n",
+                    assName(speaker),
+                    SPEAKER_COLOURS[i % SPEAKER_COLOURS.len()]
+                );
+            }
+            out += "\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n";
+            for cue in cues {
+                let style = match (showSpeakers, &cue.speaker) {
+                    (true, Some(speaker)) => assName(speaker),
+                    _ => "Default".into(),
+                };
+                let text: Vec<String> = cue.lines.iter().map(|l| assEscape(l)).collect();
+                out += &format!(
+                    "Dialogue: 0,{},{},{},{},0,0,0,,{}\n",
+                    assTimestamp(cue.start),
+                    assTimestamp(cue.end),
+                    style,
+                    cue.speaker.as_deref().map(assName).unwrap_or_default(),
+                    text.join("\\N")
+                );
+            }
+            Ok(out)
+        }
+        _ => Err(format!("unsupported subtitle format: {}", format)),
+    }
+}
+
+// HH:MM:SS,mmm for SRT, HH:MM:SS.mmm for WebVTT
+fn timestamp(seconds: f64, separator: char) -> String {
+    let ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!("{:02}:{:02}:{:02}{}{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, separator, ms % 1000)
+}
+
+// H:MM:SS.cc
+fn assTimestamp(seconds: f64) -> String {
+    let cs = (seconds.max(0.0) * 100.0).round() as u64;
+    format!("{}:{:02}:{:02}.{:02}", cs / 360_000, cs / 6000 % 60, cs / 100 % 60, cs % 100)
+}
+
+// braces start ASS override tags
+fn assEscape(text: &str) -> String {
+    text.replace('{', "(").replace('}', ")")
+}
+
+// commas separate the fields before the text
+fn assName(name: &str) -> String {
+    assEscape(name).replace(',', " ")
+}
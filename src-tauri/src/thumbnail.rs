@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use image::imageops;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use super::audio::Span;
+use super::captions::Title;
+use super::{fit, Scene};
+
+/// Stills from a scene: a single frame, or a contact sheet of frames spread through it.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ThumbnailSettings {
+    pub frame: Option<usize>,                        // the frame to use; picked automatically if unset
+    pub pick: Option<String>,                        // "speakers" (default) | "middle"
+    pub speakers: Option<HashMap<String, Vec<Span>>>, // diarisation for "speakers", over the scene's own
+    pub sheet: Option<ContactSheet>,                 // a grid of frames instead of one
+    pub sizes: Option<Vec<ThumbnailSize>>,           // one file per size, default the canvas size
+    pub format: Option<String>,                      // "png" (default) | "jpeg"
+    pub quality: Option<u8>,                         // jpeg only, 1-100, default 90
+    pub background: Option<[u8; 4]>,                 // behind transparent pixels and between cells, default black
+    pub title: Option<Title>,
+    pub output: Option<String>,                      // path template, as RenderSettings.output
+    pub overwrite: Option<String>,                   // as RenderSettings.overwrite
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ThumbnailSize {
+    pub width: u32,
+    pub height: Option<u32>, // cropped to fill if set, otherwise keeps the aspect ratio
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ContactSheet {
+    pub count: Option<usize>,   // frames, default 12
+    pub columns: Option<usize>, // default enough for a roughly square grid
+    pub gap: Option<u32>,       // px between cells, at canvas scale, default 8
+}
+
+/// The frames a thumbnail needs, in the order they're laid out.
+pub fn pickFrames(scene: &Scene, settings: &ThumbnailSettings) -> Result<Vec<usize>, String> {
+    let frameCount = scene.frames.len();
+    if frameCount == 0 {
+        return Err("scene has no frames".into());
+    }
+
+    if let Some(sheet) = &settings.sheet {
+        // the middle of each of `count` equal slices of the scene
+        let count = sheet.count.unwrap_or(12).clamp(1, frameCount);
+        return Ok((0..count).map(|i| (2 * i + 1) * frameCount / (2 * count)).collect());
+    }
+
+    if let Some(frame) = settings.frame {
+        if frame >= frameCount {
+            return Err(format!("frame {} is out of range for {} frames", frame, frameCount));
+        }
+        return Ok(vec![frame]);
+    }
+
+    let middle = frameCount / 2;
+    match settings.pick.as_deref().unwrap_or("speakers") {
+        "middle" => Ok(vec![middle]),
+        "speakers" => {
+            let diarisation = settings
+                .speakers
+                .as_ref()
+                .or(scene.captions.as_ref().map(|c| &c.analysis.speakers))
+                .or(scene.music.as_ref().and_then(|m| m.ducking.as_ref()?.spans.as_ref()));
+            let Some(diarisation) = diarisation else {
+                return Ok(vec![middle]);
+            };
+
+            // the frame with the most people talking, nearest the middle on a tie
+            let fps = scene.fps.max(1) as f64;
+            let talking = |frame: usize| {
+                let time = frame as f64 / fps;
+                diarisation
+                    .values()
+                    .filter(|spans| spans.iter().any(|s| s.start <= time && time < s.end))
+                    .count()
+            };
+            Ok(vec![(0..frameCount)
+                .max_by_key(|&frame| (talking(frame), std::cmp::Reverse(frame.abs_diff(middle))))
+                .unwrap_or(middle)])
+        }
+        pick => Err(format!("unknown thumbnail pick: {}", pick)),
+    }
+}
+
+/// The size of the still before it's scaled: a frame, or a contact sheet of `count` of them.
+pub fn stillSize(canvas: (u32, u32), count: usize, sheet: Option<&ContactSheet>) -> (u32, u32) {
+    let Some(sheet) = sheet else {
+        return canvas;
+    };
+    let (columns, rows, gap) = grid(count, sheet);
+    (
+        columns as u32 * canvas.0 + (columns as u32 + 1) * gap,
+        rows as u32 * canvas.1 + (rows as u32 + 1) * gap,
+    )
+}
+
+// columns, rows and gap of a contact sheet
+fn grid(count: usize, sheet: &ContactSheet) -> (usize, usize, u32) {
+    let count = count.max(1);
+    let columns = sheet.columns.unwrap_or((count as f64).sqrt().ceil() as usize).clamp(1, count);
+    (columns, count.div_ceil(columns), sheet.gap.unwrap_or(8))
+}
+
+/// Lays frames out in a grid, left to right then top to bottom, over `background`.
+pub fn contactSheet(frames: &[RgbaImage], sheet: &ContactSheet, background: [u8; 4]) -> RgbaImage {
+    let cell = frames.first().map(|f| f.dimensions()).unwrap_or((0, 0));
+    let (width, height) = stillSize(cell, frames.len(), Some(sheet));
+    let (columns, _, gap) = grid(frames.len(), sheet);
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba(background));
+    for (i, frame) in frames.iter().enumerate() {
+        let x = gap + (i % columns) as u32 * (cell.0 + gap);
+        let y = gap + (i / columns) as u32 * (cell.1 + gap);
+        imageops::overlay(&mut canvas, frame, x as i64, y as i64);
+    }
+    canvas
+}
+
+impl ThumbnailSize {
+    /// The size a still of `size` comes out at.
+    pub fn dimensions(&self, size: (u32, u32)) -> (u32, u32) {
+        let height = self
+            .height
+            .unwrap_or(((size.1 as f64 * self.width as f64 / size.0.max(1) as f64).round() as u32).max(1));
+        (self.width, height)
+    }
+
+    /// Scales a still to this size, cropping to fill if the aspect ratio differs.
+    pub fn resize(&self, image: &RgbaImage) -> Result<RgbaImage, String> {
+        let (width, height) = self.dimensions(image.dimensions());
+        fit::fitImage(image, width, height, "cover", fit::TRANSPARENT, None)
+    }
+}
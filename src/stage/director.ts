@@ -56,7 +56,8 @@ export function scriptFromTemplate(
                         ? datetime.getHours() * 3600 + datetime.getMinutes() * 60 + datetime.getSeconds()
                         : 0;
                     const i = ['skybox'].includes(template.background.id) ? (secondsOfDay / 86400) : frame;
-                    prop.colour = template.background.paths.colour(i, datetime) as [number, number, number];
+                    const colour = template.background.paths.colour(i, datetime) as [number, number, number];
+                    prop.colour = [Math.round(colour[0]), Math.round(colour[1]), Math.round(colour[2])];
                 }
             }
             if (template.background.propType === 'precomposed') {
@@ -132,19 +133,9 @@ export function scriptFromTemplate(
             const px = origin.x + Math.round(normX * canvasW);
             const py = origin.y + Math.round(normY * canvasH);
 
-            const frameTimeSec = frame / template.meta.fps;
-            let assetFrame = Math.floor(frameTimeSec * customAsset.fps);
-            const totalFrames = customAsset.totalFrames;
-            if (customAsset.loop) {
-                assetFrame = assetFrame % totalFrames;
-            }
-            else {
-                assetFrame = Math.min(assetFrame, totalFrames - 1);
-            }
-
+            // clip frame is chosen from the scene time, see Prop.playback
             props.push({
                 prop: id,
-                sprite: assetFrame,
                 x: px,
                 y: py,
                 width: template.video.width,
@@ -343,6 +334,10 @@ export async function sceneFromTemplate(
 
                     width: template.video.width,
                     height: template.video.height,
+                    playback: {
+                        loopMode: prop.loop ? 'loop' : 'clamp',
+                    },
+                    muted: true, // its audio is already the scene's audio track
                 };
             }
         }
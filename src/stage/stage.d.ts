@@ -1,5 +1,8 @@
-export type PropType = 'image' | 'video' | 'precomposed' | 'colour';
-export type CompositeType = 'overlay' | 'paste';
+import { Diarisation, Span } from '../utils';
+
+export type PropType = 'image' | 'video' | 'imageSequence' | 'precomposed' | 'colour';
+export type CompositeType = 'overlay' | 'paste' | 'add' | 'screen' | 'multiply';
+export type ColourSpace = 'srgb' | 'displayP3' | 'bt2020';
 
 export interface Scene {
     id: string;
@@ -9,11 +12,22 @@ export interface Scene {
         width: number;
         height: number;
     };
+    colourSpace?: ColourSpace; // working colour space, defaults to 'srgb'; the root scene's applies to all precomputes
+    background?: [number, number, number, number]; // RGBA canvas fill, transparent by default
     props: Record<string, Prop>;
-    audio: string;
-    
-    precompute: Scene[];
+    audio?: string;             // relative to public/
+    audioTracks?: AudioTrack[]; // mixed with audio and the audio of video props on stage
+    music?: MusicBed;           // ducked under everything else
+    captions?: Captions;        // burned into every frame, over everything else
+    metadata?: Metadata;        // container tags and chapters; RenderSettings.metadata overrides it field by field
+    watermark?: boolean;        // stamp DRAFT on every frame; set by draft renders
+
+    precompute: Scene[]; // may reference any other precompute in the tree by id
     frames: Script[];
+
+    // how a parent draws this scene when used as a precompute
+    compositeType?: CompositeType; // defaults to 'paste'
+    opacity?: number;              // 0-1
 }
 
 export interface Script {
@@ -23,25 +37,227 @@ export interface Script {
 
 export interface Prop {
     id: string;
-    sprites: string[];
+    sprites: string[];  // imageSequence: [directory | printf/glob pattern]
     propType: PropType;
     compositeType: CompositeType;
+    opacity?: number;   // 0-1
 
     width?: number;
     height?: number;
 
     colour?: [number, number, number];
+    playback?: Playback;
+    fps?: number;       // imageSequence only, plays by time when set
+
+    fit?: FitMode;                                  // video defaults to 'stretch'; images are only fitted if set
+    letterbox?: [number, number, number, number];   // RGBA, for 'contain' and 'none'
+    crop?: Rect;                                    // px in source, applied before fitting
+    scale?: number;                                 // image and imageSequence only: resamples sprites by this after fitting
+    sourceColourSpace?: 'bt709' | 'bt601' | 'bt2020' | 'srgb' | 'displayP3'; // video only; defaults to the stream's tags, then bt709
+
+    muted?: boolean;    // video only; otherwise its audio plays while it's on stage
+    audioGain?: number; // dB
+}
+
+export interface AudioTrack {
+    source: string;     // relative to public/
+    offset?: number;    // s, scene time at which the track starts
+    inPoint?: number;   // s, track time
+    outPoint?: number;  // s, track time
+    gain?: number;      // dB
+}
+
+export interface MusicBed extends AudioTrack {
+    loop?: boolean;     // repeat until the end of the scene
+    ducking?: Ducking;
+}
+
+export interface Ducking {
+    mode?: 'spans' | 'sidechain';    // default 'spans'
+    depth?: number;                  // dB the music drops under speech, default 12
+    attack?: number;                 // s, default 0.2
+    release?: number;                // s, default 0.8
+    threshold?: number;              // sidechain only: dialogue level in dBFS, default -40
+    spans?: Record<string, Span[]>;  // spans only: diarisation from analyseAudio, in scene time
+}
+
+export type FitMode = 'stretch' | 'contain' | 'cover' | 'none';
+
+export interface Rect {
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+}
+
+export type LoopMode = 'clamp' | 'loop' | 'pingPong';
+
+export interface Playback {
+    startTime?: number; // s, scene time at which the clip starts
+    inPoint?: number;   // s, clip time
+    outPoint?: number;  // s, clip time
+    rate?: number;
+    reverse?: boolean;
+    loopMode?: LoopMode;
 }
 
 export interface StageDirection {
     id?: string;
     prop: Prop['id'];
-    sprite?: number;
+    sprite?: number;    // for video props, overrides time-based playback
 
     // common
     x: number;          // px top-left
     y: number;          // px top-left
     width?: number;     // px
     height?: number;    // px
-    colour?: [number, number, number];
+    colour?: [number, number, number]; // overrides a colour prop's fill for this frame
+}
+
+export type RenderTarget = 'youtube' | 'vimeo' | 'instagram' | 'tiktok' | 'web' | 'master' | 'webAlpha' | 'masterAlpha';
+
+export interface RenderSettings {
+    target?: RenderTarget;      // named preset; other fields override it
+    codec?: 'h264' | 'h265' | 'vp9' | 'av1' | 'prores' | 'png';
+    crf?: number;
+    bitrate?: string;           // e.g. '8M', takes priority over crf
+    preset?: string;            // encoder speed preset, or ProRes profile
+    pixelFormat?: string;
+    gopSize?: number;           // frames
+    audioCodec?: 'aac' | 'opus' | 'mp3' | 'flac' | 'pcm';
+    audioBitrate?: string;      // e.g. '192k'
+    container?: 'mp4' | 'mov' | 'webm' | 'mkv';
+    alpha?: boolean;            // keep transparency; ProRes 4444, VP9 or PNG only
+    sequence?: SequenceSettings; // write numbered images instead of a video
+    start?: number;             // first frame to render, inclusive; with segments, forces a re-encode from here
+    end?: number;               // last frame to render, exclusive
+    segmentFrames?: number;     // checkpointed segments, joined without re-encoding; unset renders in one go
+    output?: string;            // path template relative to bin/, with {id} {date} {preset} {resolution} {version}; a folder for sequences
+    overwrite?: 'fail' | 'version' | 'overwrite'; // when the output exists; default 'version', or 'overwrite' for sequences, which resume
+    subtitles?: SubtitleTrack[]; // soft subtitle tracks
+    draft?: DraftSettings;      // render small and fast, to check timing; output is named {id}_draft
+    metadata?: Metadata;        // container tags and chapters, over the scene's
+}
+
+export interface Metadata {
+    title?: string;
+    episode?: string;           // e.g. 'S01E04'
+    artist?: string;
+    comment?: string;
+    creationTime?: string;      // ISO 8601; defaults to the source video's (VideoData.datetime)
+    source?: string;            // video prop whose creation time is carried over, default the first by id
+    chapters?: Chapters;
+}
+
+export interface Chapters {
+    markers?: Chapter[];        // given outright, over generate
+    generate?: 'speakers' | 'pauses'; // from the transcript, at speaker changes or long silences; there's no topic
+                                      // detection, so 'pauses' stands in for topic changes, titled with the words after each
+    analysis?: Captions['analysis']; // analyseAudio's result; defaults to the captions'
+    grouping?: SubtitleSettings; // how words are grouped into cues, and speaker names
+    minLength?: number;         // s, shortest generated chapter, default 30
+    pause?: number;             // 'pauses' only: s of silence that starts a chapter, default 2
+}
+
+export interface Chapter {
+    start: number;              // scene time (s); each chapter runs until the next
+    title: string;
+}
+
+export interface DraftSettings {
+    scale?: number;             // fraction of the full resolution, 0-1, default 0.5
+    fps?: number;               // drop frames down to at most this rate, keeping a whole number per second
+    watermark?: boolean;        // stamp DRAFT on every frame, default true
+}
+
+export interface SubtitleTrack {
+    file: string;               // .srt, .vtt or .ass from exportSubtitles, relative to bin/
+    language?: string;          // ISO 639-2, e.g. 'eng'
+}
+
+export interface SubtitleSettings {
+    format?: 'srt' | 'vtt' | 'ass';
+    maxChars?: number;          // per line, default 42; cues hold up to two lines
+    maxDuration?: number;       // s per cue, default 6
+    pause?: number;             // s of silence that ends a cue, default 0.6
+    speakerNames?: Record<string, string>; // e.g. SPEAKER_00 -> 'Pengwyn'
+    showSpeakers?: boolean;     // label cues with their speaker, default true
+}
+
+export interface Captions {
+    analysis: {                 // what analyseAudio returns
+        speakers: Diarisation;
+        script: { word: string, start: number, end: number }[];
+    };
+    grouping?: SubtitleSettings; // how words are grouped into cues, and speaker names
+    font: string;               // .ttf or .otf, relative to public/
+    size?: number;              // px, default 1/18 of the canvas height
+    style?: CaptionStyle;       // for every speaker
+    speakers?: Record<string, CaptionStyle>; // per speaker name, over style
+    karaoke?: 'none' | 'word' | 'fill'; // highlight the word being spoken, or every word spoken so far; default 'word'
+    fade?: number;              // s to fade each cue in and out, default 0.1
+}
+
+export interface CaptionStyle {
+    colour?: [number, number, number, number];     // text RGBA, default white
+    highlight?: [number, number, number, number];  // the word being spoken, default yellow
+    background?: [number, number, number, number]; // box behind the text, none if unset
+    padding?: number;           // px between the text and the edge of its box
+    x?: number;                 // 0-1 across the canvas, to the centre of the text
+    y?: number;                 // 0-1 down the canvas, to the bottom of the text
+}
+
+export interface ThumbnailSettings {
+    frame?: number;             // the frame to use; picked automatically if unset
+    pick?: 'speakers' | 'middle'; // the frame with the most people talking (default), or the middle one
+    speakers?: Diarisation;     // for 'speakers', over the captions' or music ducking's diarisation
+    sheet?: ContactSheet;       // a grid of frames instead of one
+    sizes?: ThumbnailSize[];    // one file per size, default the canvas size
+    format?: 'png' | 'jpeg';
+    quality?: number;           // jpeg only, 1-100, default 90
+    background?: [number, number, number, number]; // behind transparent pixels and between cells, default black
+    title?: Title;
+    output?: string;            // path template, as RenderSettings.output
+    overwrite?: 'fail' | 'version' | 'overwrite';
+}
+
+export interface ThumbnailSize {
+    width: number;
+    height?: number;            // cropped to fill if set, otherwise keeps the aspect ratio
+}
+
+export interface ContactSheet {
+    count?: number;             // frames evenly spaced through the scene, default 12
+    columns?: number;           // default enough for a roughly square grid
+    gap?: number;               // px between cells, at canvas scale, default 8
+}
+
+export interface Title {
+    text: string;               // '\n' starts a new line
+    font: string;               // .ttf or .otf, relative to public/
+    size?: number;              // px, default 1/10 of the image height
+    style?: CaptionStyle;       // highlight is unused
+}
+
+export interface SequenceSettings {
+    format?: 'png' | 'tiff' | 'exr';
+    bitDepth?: 8 | 16 | 32;     // 8 or 16 for png and tiff; exr is always 32-bit float
+    startNumber?: number;       // number of the first frame, default 0
+    padding?: number;           // digits in the frame number, default 5
+    prefix?: string;            // defaults to the scene id
+}
+
+export interface AnimationSettings {
+    format?: 'gif' | 'webp';
+    start?: number;             // first frame, inclusive
+    end?: number;               // last frame, exclusive
+    width?: number;             // scale down to this width, keeping the aspect ratio
+    fps?: number;               // drop frames down to this rate
+    colours?: number;           // gif palette size, 2-256, default 256
+    dither?: 'none' | 'bayer' | 'floydSteinberg' | 'sierra';
+    dedup?: boolean;            // merge identical consecutive frames, default true
+    quality?: number;           // webp quality, 0-100, default 75
+    maxBytes?: number;          // cut colours or quality, then size, until the file fits
+    output?: string;            // path template, as RenderSettings.output
+    overwrite?: 'fail' | 'version' | 'overwrite';
 }